@@ -0,0 +1,403 @@
+use hyper::body::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::paste::BundleEntry;
+
+/// Bytes read from the blob at a time.
+const CHUNK: usize = 1 << 20;
+
+const TAR_BLOCK: u64 = 512;
+
+/// How the files of a bundle are packed into a single download. Both formats store files as
+/// they are, so an archive is streamed straight from the bundle's blob and its length is known up
+/// front.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Files of 8 GiB and up get their size in GNU's base-256 form, as ustar's octal field
+    /// stops short of it.
+    Tar,
+    /// Without the zip64 extensions, so limited to 65535 files and 4 GiB.
+    Zip,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Tar => "application/x-tar",
+            Format::Zip => "application/zip",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Tar => "tar",
+            Format::Zip => "zip",
+        }
+    }
+
+    /// The length of the archive of `entries`, or `None` if they don't fit in the format.
+    pub fn len(self, entries: &[BundleEntry]) -> Option<u64> {
+        match self {
+            Format::Tar => Some(
+                entries
+                    .iter()
+                    .map(|entry| TAR_BLOCK + tar_padded(entry.size))
+                    .sum::<u64>()
+                    + 2 * TAR_BLOCK,
+            ),
+            Format::Zip => {
+                let len = entries
+                    .iter()
+                    .map(|entry| {
+                        let name = entry.name.len() as u64;
+                        30 + name + entry.size + 16 + 46 + name
+                    })
+                    .sum::<u64>()
+                    + 22;
+                if entries.len() > u16::MAX as usize || len > u32::MAX as u64 {
+                    None
+                } else {
+                    Some(len)
+                }
+            }
+        }
+    }
+}
+
+fn tar_padded(size: u64) -> u64 {
+    size.div_ceil(TAR_BLOCK) * TAR_BLOCK
+}
+
+/// Writes `value` as a NUL-terminated octal number filling `field`, which it must fit.
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
+
+/// Sizes from here on need more than the 11 octal digits of a ustar size field.
+const TAR_OCTAL_SIZE_LIMIT: u64 = 1 << 33;
+
+/// Writes the 12-byte size field of a tar header.
+fn tar_size(field: &mut [u8], size: u64) {
+    if size < TAR_OCTAL_SIZE_LIMIT {
+        octal(field, size);
+    } else {
+        // a set high bit marks the rest of the field as a big-endian binary number
+        field.fill(0);
+        field[0] = 0x80;
+        field[4..].copy_from_slice(&size.to_be_bytes());
+    }
+}
+
+fn tar_header(entry: &BundleEntry, mtime: u64) -> Vec<u8> {
+    let mut header = vec![0; TAR_BLOCK as usize];
+    header[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    tar_size(&mut header[124..136], entry.size);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // the checksum is taken with its own field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+    octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+    header
+}
+
+/// The time and date fields of a zip header, in MS-DOS format.
+fn dos_time(unix: u64) -> (u16, u16) {
+    let at = time::OffsetDateTime::from_unix_timestamp(unix as i64)
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    if at.year() < 1980 {
+        return (0, 0x21);
+    }
+    let time = (at.hour() as u16) << 11 | (at.minute() as u16) << 5 | ((at.second() as u16) / 2);
+    let date = ((at.year() - 1980) as u16) << 9 | (at.month() as u16) << 5 | at.day() as u16;
+    (time, date)
+}
+
+/// Sizes follow the data, and names are UTF-8.
+const ZIP_FLAGS: u16 = 0x0008 | 0x0800;
+
+struct ZipHeader<'a> {
+    entry: &'a BundleEntry,
+    crc: u32,
+    offset: u32,
+}
+
+impl ZipHeader<'_> {
+    fn common(&self, out: &mut Vec<u8>, (time, date): (u16, u16)) {
+        out.extend_from_slice(&20_u16.to_le_bytes());
+        out.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+        out.extend_from_slice(&0_u16.to_le_bytes());
+        out.extend_from_slice(&time.to_le_bytes());
+        out.extend_from_slice(&date.to_le_bytes());
+        out.extend_from_slice(&self.crc.to_le_bytes());
+        out.extend_from_slice(&(self.entry.size as u32).to_le_bytes());
+        out.extend_from_slice(&(self.entry.size as u32).to_le_bytes());
+        out.extend_from_slice(&(self.entry.name.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0_u16.to_le_bytes());
+    }
+
+    fn local(&self, dos: (u16, u16)) -> Vec<u8> {
+        let mut out = 0x04034b50_u32.to_le_bytes().to_vec();
+        self.common(&mut out, dos);
+        out.extend_from_slice(self.entry.name.as_bytes());
+        out
+    }
+
+    fn descriptor(&self) -> Vec<u8> {
+        let mut out = 0x08074b50_u32.to_le_bytes().to_vec();
+        out.extend_from_slice(&self.crc.to_le_bytes());
+        out.extend_from_slice(&(self.entry.size as u32).to_le_bytes());
+        out.extend_from_slice(&(self.entry.size as u32).to_le_bytes());
+        out
+    }
+
+    fn central(&self, dos: (u16, u16)) -> Vec<u8> {
+        let mut out = 0x02014b50_u32.to_le_bytes().to_vec();
+        out.extend_from_slice(&20_u16.to_le_bytes());
+        self.common(&mut out, dos);
+        // comment length, disk, internal and external attributes
+        out.extend_from_slice(&[0; 10]);
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(self.entry.name.as_bytes());
+        out
+    }
+}
+
+enum Step {
+    Header,
+    Body { left: u64 },
+    Footer,
+    Trailer,
+    Done,
+}
+
+/// Produces an archive of a bundle chunk by chunk, reading the files from its blob in order.
+pub struct Archive<R> {
+    format: Format,
+    entries: Vec<BundleEntry>,
+    blob: R,
+    mtime: u64,
+    /// The entry being written, and how far along it is.
+    index: usize,
+    step: Step,
+    /// Bytes of archive produced so far.
+    written: u64,
+    /// For zip, where the entry being written starts and its CRC so far, and the central
+    /// directory headers of the finished entries.
+    entry_offset: u64,
+    crc: crc32fast::Hasher,
+    central: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> Archive<R> {
+    /// `blob` must be positioned at the start of the bundle's blob. Files get `mtime` as their
+    /// modification time.
+    pub fn new(format: Format, entries: Vec<BundleEntry>, blob: R, mtime: u64) -> Self {
+        Archive {
+            format,
+            entries,
+            blob,
+            mtime,
+            index: 0,
+            step: Step::Header,
+            written: 0,
+            entry_offset: 0,
+            crc: crc32fast::Hasher::new(),
+            central: Vec::new(),
+        }
+    }
+
+    /// The next chunk of the archive, along with how many bytes of the blob it carries, or `None`
+    /// once the archive is complete.
+    pub async fn next(&mut self) -> std::io::Result<Option<(Bytes, u64)>> {
+        loop {
+            match self.step().await? {
+                Some((chunk, _)) if chunk.is_empty() => continue,
+                Some((chunk, read)) => {
+                    self.written += chunk.len() as u64;
+                    return Ok(Some((chunk.into(), read)));
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Advances one step, which may produce nothing.
+    async fn step(&mut self) -> std::io::Result<Option<(Vec<u8>, u64)>> {
+        let chunk = match self.step {
+            Step::Header => match self.entries.get(self.index) {
+                Some(entry) => {
+                    let header = match self.format {
+                        Format::Tar => tar_header(entry, self.mtime),
+                        Format::Zip => ZipHeader {
+                            entry,
+                            crc: 0,
+                            offset: self.written as u32,
+                        }
+                        .local(dos_time(self.mtime)),
+                    };
+                    self.entry_offset = self.written;
+                    self.crc = crc32fast::Hasher::new();
+                    self.step = Step::Body { left: entry.size };
+                    (header, 0)
+                }
+                None => {
+                    self.step = Step::Trailer;
+                    (Vec::new(), 0)
+                }
+            },
+            Step::Body { left } if left > 0 => {
+                let mut buf = vec![0; (left as usize).min(CHUNK)];
+                self.blob.read_exact(&mut buf).await?;
+                if self.format == Format::Zip {
+                    self.crc.update(&buf);
+                }
+                self.step = Step::Body {
+                    left: left - buf.len() as u64,
+                };
+                let read = buf.len() as u64;
+                (buf, read)
+            }
+            Step::Body { .. } => {
+                self.step = Step::Footer;
+                (Vec::new(), 0)
+            }
+            Step::Footer => {
+                let entry = &self.entries[self.index];
+                let footer = match self.format {
+                    Format::Tar => vec![0; (tar_padded(entry.size) - entry.size) as usize],
+                    Format::Zip => {
+                        let header = ZipHeader {
+                            entry,
+                            crc: std::mem::take(&mut self.crc).finalize(),
+                            offset: self.entry_offset as u32,
+                        };
+                        self.central.extend(header.central(dos_time(self.mtime)));
+                        header.descriptor()
+                    }
+                };
+                self.index += 1;
+                self.step = Step::Header;
+                (footer, 0)
+            }
+            Step::Trailer => {
+                let trailer = match self.format {
+                    Format::Tar => vec![0; 2 * TAR_BLOCK as usize],
+                    Format::Zip => {
+                        let count = (self.entries.len() as u16).to_le_bytes();
+                        let mut trailer = std::mem::take(&mut self.central);
+                        let central_len = trailer.len() as u32;
+                        let central_offset = self.written as u32;
+                        trailer.extend_from_slice(&0x06054b50_u32.to_le_bytes());
+                        trailer.extend_from_slice(&[0; 4]);
+                        trailer.extend_from_slice(&count);
+                        trailer.extend_from_slice(&count);
+                        trailer.extend_from_slice(&central_len.to_le_bytes());
+                        trailer.extend_from_slice(&central_offset.to_le_bytes());
+                        trailer.extend_from_slice(&[0; 2]);
+                        trailer
+                    }
+                };
+                self.step = Step::Done;
+                (trailer, 0)
+            }
+            Step::Done => return Ok(None),
+        };
+        Ok(Some(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64) -> BundleEntry {
+        BundleEntry {
+            name: name.to_owned(),
+            content_type: "text/plain".to_owned(),
+            offset: 0,
+            size,
+        }
+    }
+
+    fn parse_octal(field: &[u8]) -> u64 {
+        let digits = std::str::from_utf8(field).unwrap();
+        u64::from_str_radix(digits.trim_end_matches(['\0', ' ']), 8).unwrap()
+    }
+
+    #[test]
+    fn tar_header_holds_name_size_and_checksum() {
+        let header = tar_header(&entry("notes.txt", 1000), 1_600_000_000);
+        assert_eq!(header.len(), TAR_BLOCK as usize);
+        assert_eq!(&header[..10], b"notes.txt\0");
+        assert_eq!(&header[124..136], b"00000001750\0");
+        assert_eq!(parse_octal(&header[136..148]), 1_600_000_000);
+        assert_eq!(&header[257..263], b"ustar\0");
+        assert_eq!(&header[263..265], b"00");
+        let mut blank = header.clone();
+        blank[148..156].copy_from_slice(b"        ");
+        let sum: u64 = blank.iter().map(|&b| b as u64).sum();
+        assert_eq!(parse_octal(&header[148..156]), sum);
+    }
+
+    #[test]
+    fn tar_sizes_switch_to_base_256_at_8_gib() {
+        let largest = tar_header(&entry("a", TAR_OCTAL_SIZE_LIMIT - 1), 0);
+        assert_eq!(&largest[124..136], b"77777777777\0");
+        for size in [TAR_OCTAL_SIZE_LIMIT, 5 << 40] {
+            let header = tar_header(&entry("a", size), 0);
+            assert_eq!(header[124], 0x80);
+            assert_eq!(&header[125..128], &[0; 3]);
+            assert_eq!(header[128..136], size.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn tar_len_counts_headers_padding_and_trailer() {
+        assert_eq!(Format::Tar.len(&[]), Some(1024));
+        assert_eq!(
+            Format::Tar.len(&[entry("a", 0), entry("b", 512), entry("c", 513)]),
+            Some(3 * 512 + 512 + 1024 + 1024)
+        );
+        assert_eq!(
+            Format::Tar.len(&[entry("a", TAR_OCTAL_SIZE_LIMIT)]),
+            Some(512 + TAR_OCTAL_SIZE_LIMIT + 1024)
+        );
+    }
+
+    #[test]
+    fn zip_len_refuses_what_needs_zip64() {
+        assert_eq!(
+            Format::Zip.len(&[entry("a", 10)]),
+            Some(30 + 1 + 10 + 16 + 46 + 1 + 22)
+        );
+        assert_eq!(Format::Zip.len(&[entry("a", u32::MAX as u64)]), None);
+        let many = vec![entry("a", 0); u16::MAX as usize + 1];
+        assert_eq!(Format::Zip.len(&many), None);
+    }
+
+    #[tokio::test]
+    async fn archives_are_as_long_as_promised() {
+        let entries = vec![entry("a", 3), entry("b", 0), entry("c", 700)];
+        let blob = vec![7; 703];
+        for format in [Format::Tar, Format::Zip] {
+            let mut archive = Archive::new(format, entries.clone(), blob.as_slice(), 0);
+            let (mut written, mut read) = (0, 0);
+            while let Some((chunk, n)) = archive.next().await.unwrap() {
+                written += chunk.len() as u64;
+                read += n;
+            }
+            assert_eq!(Some(written), format.len(&entries));
+            assert_eq!(read, 703);
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+use crate::paste::{self, PasteRecord, Storage, View};
+use crate::receipt::{Outcome, Receipts};
+use crate::store::Store;
+use crate::Error;
+
+/// The part of a big paste a `Range` header asks for.
+pub enum ByteRange {
+    /// No range, or one that can't be served as a single part: the whole body.
+    Full,
+    Partial(Range<u64>),
+    /// A range that lies entirely past the end of the body.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses a `Range` header against a body of `len` bytes. Only single byte ranges are served;
+    /// anything else, including a malformed header, is ignored as HTTP allows.
+    pub fn parse(header: Option<&str>, len: u64) -> ByteRange {
+        let spec = match header.and_then(|header| header.trim().strip_prefix("bytes=")) {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return ByteRange::Full,
+        };
+        let (first, last) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return ByteRange::Full,
+        };
+        let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => first..last.saturating_add(1).min(len),
+            (Ok(first), Err(_)) if last.is_empty() => first..len,
+            (Err(_), Ok(suffix)) if first.is_empty() => len - suffix.min(len)..len,
+            _ => return ByteRange::Full,
+        };
+        if range.start >= range.end {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(range)
+        }
+    }
+}
+
+/// Downloads of big pastes in progress. A download takes its view of the paste once every byte
+/// has been delivered, over as many range requests as it takes, or once `grace` seconds have
+/// passed since it began, whichever comes first. Only one download of a paste runs at a time: the
+/// request that begins it is handed a token, and only requests carrying that token may continue
+/// it. The start of each download and the hash of its token are kept in the record so both
+/// outlive a restart; the bytes delivered so far are only kept in memory.
+#[derive(Clone)]
+pub struct Downloads {
+    pastes: Store,
+    receipts: Receipts,
+    state: Arc<Mutex<State>>,
+    grace: u64,
+    logger: Arc<slog::Logger>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The download awaiting each paste's next view.
+    progress: HashMap<String, Progress>,
+    /// How many responses are reading each blob.
+    readers: HashMap<String, usize>,
+    /// Blobs to remove once their last reader is done.
+    doomed: HashSet<String>,
+}
+
+struct Progress {
+    started: u64,
+    /// Disjoint delivered ranges, in order.
+    delivered: Vec<Range<u64>>,
+}
+
+impl Progress {
+    /// Adds a delivered range, returning whether all of `0..len` has now been delivered.
+    fn deliver(&mut self, range: Range<u64>, len: u64) -> bool {
+        let mut merged = range;
+        self.delivered.retain(|r| {
+            if r.start <= merged.end && merged.start <= r.end {
+                merged = merged.start.min(r.start)..merged.end.max(r.end);
+                false
+            } else {
+                true
+            }
+        });
+        let at = self
+            .delivered
+            .iter()
+            .position(|r| r.start > merged.start)
+            .unwrap_or(self.delivered.len());
+        self.delivered.insert(at, merged);
+        self.delivered.first() == Some(&(0..len))
+    }
+}
+
+/// What came of asking to read a big paste.
+// only ever returned, never kept, so its size hardly matters
+#[allow(clippy::large_enum_variant)]
+pub enum Begun {
+    /// There is no big paste by that key.
+    Missing,
+    /// Another download of the paste is under way, and the request didn't carry its token.
+    Busy,
+    Delivering(PasteRecord, Delivery),
+}
+
+/// A response reading a paste's blob. Its blob is kept until every delivery is dropped, at which
+/// point the bytes it sent count towards the download it belongs to.
+pub struct Delivery {
+    downloads: Downloads,
+    key: String,
+    started: u64,
+    /// Length of the whole blob.
+    pub len: u64,
+    /// Offset of the first byte sent.
+    pub start: u64,
+    /// Bytes sent so far.
+    pub sent: u64,
+    /// The token continuing this download, handed out once by the request that began it.
+    pub token: Option<String>,
+}
+
+impl Drop for Delivery {
+    fn drop(&mut self) {
+        let key = std::mem::take(&mut self.key);
+        let complete = self.sent > 0
+            && self.downloads.delivered(
+                &key,
+                self.started,
+                self.start..self.start + self.sent,
+                self.len,
+            );
+        let remove = self.downloads.release(&key);
+        if complete || remove {
+            let downloads = self.downloads.clone();
+            let started = self.started;
+            tokio::spawn(async move {
+                if complete {
+                    downloads.finish(&key, started).await;
+                }
+                if remove {
+                    downloads.remove_now(&key).await;
+                }
+            });
+        }
+    }
+}
+
+impl Downloads {
+    pub fn new(pastes: Store, receipts: Receipts, grace: u64, logger: Arc<slog::Logger>) -> Self {
+        Downloads {
+            pastes,
+            receipts,
+            state: Default::default(),
+            grace,
+            logger,
+        }
+    }
+
+    /// Picks up the downloads that were in progress before a restart, returning how many.
+    pub fn resume(&self) -> Result<usize, Error> {
+        let mut resumed = 0;
+        for (key, record) in self.pastes.records()? {
+            if let Some(started) = record.downloading_since {
+                self.watch(key, started);
+                resumed += 1;
+            }
+        }
+        Ok(resumed)
+    }
+
+    /// Starts a download of a big paste, or continues the one in progress when `resume` is its
+    /// token, returning the record along with a delivery for the response to send.
+    pub fn begin(&self, key: &str, resume: Option<&str>, now: u64) -> Result<Begun, Error> {
+        // counted as a reader first, so a view taken meanwhile can't remove the blob under us
+        let mut delivery = self.open(key);
+        let (token, token_hash) = crate::new_token();
+        let record = match self.pastes.update(key, &|record| {
+            if record.downloading_since.is_none() {
+                record.downloading_since = Some(now);
+                record.download_token_hash = Some(token_hash.clone());
+            }
+        })? {
+            Some(record) if record.storage == Storage::Big => record,
+            _ => return Ok(Begun::Missing),
+        };
+        if record.download_token_hash.as_ref() == Some(&token_hash) {
+            delivery.token = Some(token);
+        } else {
+            let resume_hash =
+                resume.map(|resume| Sha256::digest(resume.trim().as_bytes()).to_vec());
+            // downloads begun before tokens were handed out can't be continued, only wait out
+            // their grace period
+            if resume_hash.is_none() || record.download_token_hash != resume_hash {
+                return Ok(Begun::Busy);
+            }
+        }
+        delivery.started = record.downloading_since.unwrap_or(now);
+        self.watch(key.to_owned(), delivery.started);
+        Ok(Begun::Delivering(record, delivery))
+    }
+
+    fn open(&self, key: &str) -> Delivery {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .readers
+            .entry(key.to_owned())
+            .or_default() += 1;
+        Delivery {
+            downloads: self.clone(),
+            key: key.to_owned(),
+            started: 0,
+            len: 0,
+            start: 0,
+            sent: 0,
+            token: None,
+        }
+    }
+
+    /// Tracks the download that began at `started`, and schedules the end of its grace period.
+    fn watch(&self, key: String, started: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.progress.get(&key).map(|p| p.started) == Some(started) {
+            return;
+        }
+        state.progress.insert(
+            key.clone(),
+            Progress {
+                started,
+                delivered: Vec::new(),
+            },
+        );
+        let downloads = self.clone();
+        let delay = (started + self.grace).saturating_sub(paste::now());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+            downloads.finish(&key, started).await;
+        });
+    }
+
+    /// Records bytes sent for the download that began at `started`, returning whether it has now
+    /// delivered the whole paste.
+    fn delivered(&self, key: &str, started: u64, range: Range<u64>, len: u64) -> bool {
+        match self.state.lock().unwrap().progress.get_mut(key) {
+            Some(progress) if progress.started == started => progress.deliver(range, len),
+            _ => false,
+        }
+    }
+
+    /// Drops a reader, returning whether its blob is now due for removal.
+    fn release(&self, key: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if let Some(readers) = state.readers.get_mut(key) {
+            *readers -= 1;
+            if *readers > 0 {
+                return false;
+            }
+            state.readers.remove(key);
+        }
+        state.doomed.remove(key)
+    }
+
+    /// Takes the view that the download begun at `started` was waiting for. Whichever of its
+    /// completion and the end of its grace period comes first takes the view; the other does
+    /// nothing.
+    async fn finish(&self, key: &str, started: u64) {
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.progress.get(key) {
+                Some(progress) if progress.started == started => state.progress.remove(key),
+                _ => return,
+            };
+        }
+        let res = async {
+            let now = paste::now();
+            let remaining = match self.pastes.view(key, now).await? {
+                View::Live { record, .. } => {
+                    if record.remaining_views == 0 {
+                        self.remove_blob(key).await?;
+                    }
+                    self.receipts.viewed(key, &record, now);
+                    Some(record.remaining_views)
+                }
+                View::Expired(record) => {
+                    self.remove_blob(key).await?;
+                    self.receipts.ended(key, &record, Outcome::Expired, now);
+                    None
+                }
+                View::Missing => return Ok(()),
+            };
+            self.pastes.flush().await?;
+            slog::info!(
+                self.logger,
+                "VIEW";
+                "key" => key,
+                "remaining-views" => remaining,
+            );
+            Ok::<_, Error>(())
+        };
+        if let Err(e) = res.await {
+            slog::error!(
+                self.logger,
+                "ERROR";
+                "context" => "finish download",
+                "key" => key,
+                "reason" => %e,
+            );
+        }
+    }
+
+    /// Removes the blob of a paste that is gone, waiting for any responses still reading it.
+    pub async fn remove_blob(&self, key: &str) -> Result<(), Error> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.progress.remove(key);
+            if state.readers.contains_key(key) {
+                // a secure erase would otherwise overwrite it mid-stream
+                state.doomed.insert(key.to_owned());
+                return Ok(());
+            }
+        }
+        self.pastes.blobs().remove(key).await
+    }
+
+    async fn remove_now(&self, key: &str) {
+        if let Err(e) = self.pastes.blobs().remove(key).await {
+            slog::error!(
+                self.logger,
+                "ERROR";
+                "context" => "remove burned blob",
+                "key" => key,
+                "reason" => %e,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &str, len: u64) -> Option<Range<u64>> {
+        match ByteRange::parse(Some(header), len) {
+            ByteRange::Partial(range) => Some(range),
+            _ => None,
+        }
+    }
+
+    fn progress() -> Progress {
+        Progress {
+            started: 0,
+            delivered: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_closed_ranges() {
+        assert_eq!(parse("bytes=0-9", 100), Some(0..10));
+        assert_eq!(parse(" bytes= 10-10 ", 100), Some(10..11));
+        // clamped to the end of the body
+        assert_eq!(parse("bytes=90-200", 100), Some(90..100));
+    }
+
+    #[test]
+    fn parses_open_ended_ranges() {
+        assert_eq!(parse("bytes=40-", 100), Some(40..100));
+        assert_eq!(parse("bytes=0-", 100), Some(0..100));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse("bytes=-10", 100), Some(90..100));
+        assert_eq!(parse("bytes=-500", 100), Some(0..100));
+    }
+
+    #[test]
+    fn ignores_what_it_cannot_serve() {
+        for header in [
+            "bytes=9-0",
+            "bytes=0-1,4-5",
+            "items=0-1",
+            "bytes=x-1",
+            "bytes=5",
+            "",
+        ] {
+            assert!(
+                matches!(ByteRange::parse(Some(header), 100), ByteRange::Full),
+                "{}",
+                header
+            );
+        }
+        assert!(matches!(ByteRange::parse(None, 100), ByteRange::Full));
+    }
+
+    #[test]
+    fn refuses_unsatisfiable_ranges() {
+        for header in ["bytes=100-", "bytes=100-200", "bytes=-0"] {
+            assert!(
+                matches!(
+                    ByteRange::parse(Some(header), 100),
+                    ByteRange::Unsatisfiable
+                ),
+                "{}",
+                header
+            );
+        }
+        assert!(matches!(
+            ByteRange::parse(Some("bytes=0-"), 0),
+            ByteRange::Unsatisfiable
+        ));
+    }
+
+    #[test]
+    fn completes_in_one_delivery() {
+        let mut progress = progress();
+        assert!(progress.deliver(0..100, 100));
+    }
+
+    #[test]
+    fn merges_overlapping_deliveries() {
+        let mut progress = progress();
+        assert!(!progress.deliver(0..60, 100));
+        assert!(!progress.deliver(50..90, 100));
+        assert_eq!(progress.delivered, vec![0..90]);
+        assert!(progress.deliver(20..100, 100));
+    }
+
+    #[test]
+    fn merges_adjacent_deliveries() {
+        let mut progress = progress();
+        assert!(!progress.deliver(0..50, 100));
+        assert!(progress.deliver(50..100, 100));
+        assert_eq!(progress.delivered, vec![0..100]);
+    }
+
+    #[test]
+    fn merges_out_of_order_deliveries() {
+        let mut progress = progress();
+        assert!(!progress.deliver(80..100, 100));
+        assert!(!progress.deliver(20..40, 100));
+        assert!(!progress.deliver(50..60, 100));
+        assert_eq!(progress.delivered, vec![20..40, 50..60, 80..100]);
+        // one delivery bridging several gaps
+        assert!(!progress.deliver(30..90, 100));
+        assert_eq!(progress.delivered, vec![20..100]);
+        assert!(progress.deliver(0..20, 100));
+    }
+
+    #[test]
+    fn completes_only_once_all_is_covered() {
+        // the end without the start
+        assert!(!progress().deliver(1..100, 100));
+        let mut progress = progress();
+        // the start without the end
+        assert!(!progress.deliver(0..99, 100));
+        // a repeat adds nothing
+        assert!(!progress.deliver(0..99, 100));
+        assert!(progress.deliver(99..100, 100));
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use age::stream::StreamWriter;
+use futures::AsyncWrite;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// How long a count of messages lasts, in seconds.
+const RATE_WINDOW: u64 = 60 * 60;
+
+/// How long a challenge may be worked on before its stamp is refused, in seconds.
+pub const CHALLENGE_LIFETIME: u64 = 10 * 60;
+
+/// How long a source token lasts, in seconds.
+pub const SOURCE_LIFETIME: u64 = 30 * 24 * 60 * 60;
+
+/// Bytes of a challenge: its timestamp, random bytes, and a truncated MAC of both.
+const CHALLENGE_LEN: usize = 8 + 16 + 16;
+
+/// How an age file starts, in its binary and armored forms.
+const AGE_MAGIC: [&[u8]; 2] = [
+    b"age-encryption.org/v1\n",
+    b"-----BEGIN AGE ENCRYPTED FILE-----",
+];
+
+/// Bytes of a message needed to tell whether it is already an age file.
+pub const MAGIC_LEN: usize = 34;
+
+/// Whether a message starting with `prefix` is an age file.
+pub fn is_age(prefix: &[u8]) -> bool {
+    AGE_MAGIC.iter().any(|magic| prefix.starts_with(magic))
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(
+        bytes,
+        base64::Config::new(base64::CharacterSet::UrlSafe, false),
+    )
+}
+
+/// Number of leading zero bits in `hash`.
+fn leading_zeros(hash: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Messages counted since `start`.
+struct Window {
+    start: u64,
+    count: u32,
+}
+
+/// A source, known only by the token it was handed.
+struct Source {
+    expires: u64,
+    window: Window,
+}
+
+#[derive(Default)]
+struct State {
+    /// Sources by the SHA-256 of their token.
+    sources: HashMap<Vec<u8>, Source>,
+    /// Challenges whose stamp has been used, until they expire.
+    spent: HashMap<Vec<u8>, u64>,
+}
+
+/// Why a stamp was refused.
+pub enum StampError {
+    Malformed,
+    /// Not a challenge from this server, or one that has expired or been used already.
+    BadChallenge,
+    TooLittleWork,
+}
+
+impl std::fmt::Display for StampError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StampError::Malformed => write!(f, "a stamp is a challenge and a nonce, joined by ':'"),
+            StampError::BadChallenge => write!(f, "the challenge is unknown, expired or used"),
+            StampError::TooLittleWork => write!(f, "the stamp does not carry enough work"),
+        }
+    }
+}
+
+/// Why a message was refused before it was read.
+pub enum Refusal {
+    UnknownSource,
+    /// The source has used up its hour, and must wait this many seconds.
+    RateLimited(u64),
+}
+
+/// A drop box that anyone can leave messages in without logging in. Every message is stored
+/// encrypted to `recipient`, whose identity the server never holds, so only the admin can read
+/// them.
+///
+/// Sources reach the server through Tor, so their addresses tell them apart no better than not
+/// at all. Instead a source first registers, by solving a proof-of-work challenge, and gets a
+/// token that its messages are counted against. Each source has its own hourly limit, and a
+/// source that wants more has to do the work again for another token. Sources are only held in
+/// memory, so after a restart they register again.
+#[derive(Clone)]
+pub struct Inbox {
+    pub recipient: age::x25519::Recipient,
+    /// Largest message accepted, in bytes.
+    pub max_size: u64,
+    /// Messages each source may leave per hour.
+    pub rate_limit: u32,
+    /// Leading zero bits asked of the SHA-256 of a stamp.
+    pub difficulty: u32,
+    /// Signs challenges, so they need not be stored until they are used. Challenges from before
+    /// a restart are no longer accepted.
+    key: Arc<[u8; 32]>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Inbox {
+    pub fn new(
+        recipient: age::x25519::Recipient,
+        max_size: u64,
+        rate_limit: u32,
+        difficulty: u32,
+    ) -> Self {
+        let mut key = [0; 32];
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut key);
+        Inbox {
+            recipient,
+            max_size,
+            rate_limit,
+            difficulty,
+            key: Arc::new(key),
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    fn mac(&self, bytes: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&*self.key).expect("HMAC takes keys of any length");
+        mac.update(bytes);
+        mac
+    }
+
+    /// A fresh challenge, issued at `now`. A stamp is the challenge, a `:` and any nonce, chosen
+    /// so that the SHA-256 of the whole stamp starts with [`difficulty`](Self::difficulty) zero
+    /// bits.
+    pub fn challenge(&self, now: u64) -> String {
+        let mut challenge = now.to_be_bytes().to_vec();
+        let mut nonce = [0; 16];
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut nonce);
+        challenge.extend_from_slice(&nonce);
+        let mac = self.mac(&challenge).finalize().into_bytes();
+        challenge.extend_from_slice(&mac[..16]);
+        encode(&challenge)
+    }
+
+    /// Registers a new source for `stamp`, returning its token. Each challenge registers one
+    /// source at most.
+    pub fn register(&self, stamp: &str, now: u64) -> Result<String, StampError> {
+        let (challenge, _) = stamp.trim().split_once(':').ok_or(StampError::Malformed)?;
+        let challenge = base64::decode_config(challenge, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| StampError::Malformed)?;
+        if challenge.len() != CHALLENGE_LEN {
+            return Err(StampError::Malformed);
+        }
+        let (signed, mac) = challenge.split_at(8 + 16);
+        self.mac(signed)
+            .verify_truncated_left(mac)
+            .map_err(|_| StampError::BadChallenge)?;
+        let issued = u64::from_be_bytes(signed[..8].try_into().expect("eight bytes"));
+        let expires = issued + CHALLENGE_LIFETIME;
+        if issued > now || expires <= now {
+            return Err(StampError::BadChallenge);
+        }
+        if leading_zeros(&Sha256::digest(stamp.trim().as_bytes())) < self.difficulty {
+            return Err(StampError::TooLittleWork);
+        }
+        let mut state = self.state.lock().unwrap();
+        state.spent.retain(|_, expires| *expires > now);
+        if state.spent.insert(challenge, expires).is_some() {
+            return Err(StampError::BadChallenge);
+        }
+        state.sources.retain(|_, source| source.expires > now);
+        let (token, token_hash) = crate::new_token();
+        state.sources.insert(
+            token_hash,
+            Source {
+                expires: now + SOURCE_LIFETIME,
+                window: Window {
+                    start: now,
+                    count: 0,
+                },
+            },
+        );
+        Ok(token)
+    }
+
+    /// Counts a message from the source holding `token` arriving at `now`. Should the message
+    /// not be stored after all, [`refund`](Self::refund) gives the count back.
+    pub fn admit(&self, token: &str, now: u64) -> Result<(), Refusal> {
+        let token_hash = Sha256::digest(token.trim().as_bytes()).to_vec();
+        let mut state = self.state.lock().unwrap();
+        let window = match state.sources.get_mut(&token_hash) {
+            Some(source) if source.expires > now => &mut source.window,
+            _ => return Err(Refusal::UnknownSource),
+        };
+        if window.start + RATE_WINDOW <= now {
+            *window = Window {
+                start: now,
+                count: 0,
+            };
+        }
+        if window.count >= self.rate_limit {
+            return Err(Refusal::RateLimited(window.start + RATE_WINDOW - now));
+        }
+        window.count += 1;
+        Ok(())
+    }
+
+    /// Takes back a message admitted at `admitted`, unless its hour is already over.
+    pub fn refund(&self, token: &str, admitted: u64) {
+        let token_hash = Sha256::digest(token.trim().as_bytes()).to_vec();
+        let mut state = self.state.lock().unwrap();
+        if let Some(source) = state.sources.get_mut(&token_hash) {
+            if source.window.start <= admitted && source.window.count > 0 {
+                source.window.count -= 1;
+            }
+        }
+    }
+
+    /// Wraps `output` so whatever is written to it is encrypted to the inbox key. The writer must
+    /// be closed, or the file is left truncated.
+    pub async fn encrypt<W: AsyncWrite + Unpin>(
+        &self,
+        output: W,
+    ) -> std::io::Result<StreamWriter<W>> {
+        age::Encryptor::with_recipients(vec![Box::new(self.recipient.clone())])
+            .expect("there is a recipient")
+            .wrap_async_output(output)
+            .await
+            .map_err(std::io::Error::other)
+    }
+}
@@ -0,0 +1,116 @@
+use std::convert::TryInto;
+
+use sled::transaction::ConflictableTransactionError;
+use sled::Transactional;
+
+use crate::Error;
+
+/// A single-use link through which someone without a login can send one paste to the user who
+/// made it.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Invite {
+    pub id: String,
+    /// The user the paste will belong to, and the only one who learns its key.
+    pub created_by: String,
+    pub created_at: u64,
+    /// Unix timestamp after which the link no longer works.
+    pub expires: u64,
+    /// Largest paste accepted, in bytes.
+    pub max_size: u64,
+    /// Shown to the uploader, and becomes the label of the paste.
+    pub note: Option<String>,
+}
+
+/// Unused invites in sled: `invites` maps the SHA-256 of each invite's token to the [`Invite`],
+/// and `invite-expirations` indexes those hashes by the big-endian expiry followed by the hash. The
+/// token itself is only ever handed to the user who made the invite.
+#[derive(Clone)]
+pub struct Invites {
+    invites: sled::Tree,
+    expirations: sled::Tree,
+}
+
+fn expiration_key(expires: u64, token_hash: &[u8]) -> Vec<u8> {
+    let mut index_key = expires.to_be_bytes().to_vec();
+    index_key.extend_from_slice(token_hash);
+    index_key
+}
+
+impl Invites {
+    pub fn open(db: &sled::Db) -> Result<Self, sled::Error> {
+        Ok(Invites {
+            invites: db.open_tree("invites")?,
+            expirations: db.open_tree("invite-expirations")?,
+        })
+    }
+
+    pub fn insert(&self, token_hash: &[u8], invite: &Invite) -> Result<(), Error> {
+        let value = serde_json::to_vec(invite)?;
+        (&self.invites, &self.expirations).transaction(|(invites, expirations)| {
+            invites.insert(token_hash, value.as_slice())?;
+            expirations.insert(expiration_key(invite.expires, token_hash), &[])?;
+            Ok::<_, ConflictableTransactionError>(())
+        })?;
+        Ok(())
+    }
+
+    /// Removes an invite, returning it.
+    pub fn remove(&self, token_hash: &[u8]) -> Result<Option<Invite>, Error> {
+        let invite = (&self.invites, &self.expirations).transaction(|(invites, expirations)| {
+            let invite = match invites.remove(token_hash)? {
+                Some(invite) => serde_json::from_slice::<Invite>(&invite)
+                    .map_err(ConflictableTransactionError::Abort)?,
+                None => return Ok(None),
+            };
+            expirations.remove(expiration_key(invite.expires, token_hash))?;
+            Ok(Some(invite))
+        })?;
+        Ok(invite)
+    }
+
+    /// Takes the invite whose token hashes to `token_hash` for an upload, so no other upload can
+    /// use it. An upload that fails should [`insert`](Self::insert) it back.
+    pub fn claim(&self, token_hash: &[u8], now: u64) -> Result<Option<Invite>, Error> {
+        Ok(self
+            .remove(token_hash)?
+            .filter(|invite| invite.expires > now))
+    }
+
+    /// Every unused invite made by `user`, with the hash of its token, soonest to expire first.
+    pub fn made_by(&self, user: &str) -> Result<Vec<(Vec<u8>, Invite)>, Error> {
+        let mut invites = Vec::new();
+        for index_key in self.expirations.iter().keys() {
+            let token_hash = index_key?[8..].to_vec();
+            if let Some(invite) = self.invites.get(&token_hash)? {
+                let invite: Invite = serde_json::from_slice(&invite)?;
+                if invite.created_by == user {
+                    invites.push((token_hash, invite));
+                }
+            }
+        }
+        Ok(invites)
+    }
+
+    /// Token hashes of every invite that has expired by `now`, in order of expiry.
+    pub fn expired(&self, now: u64) -> Result<Vec<Vec<u8>>, Error> {
+        self.expirations
+            .range(..now.to_be_bytes())
+            .keys()
+            .map(|key| Ok(key?[8..].to_vec()))
+            .collect()
+    }
+
+    /// The earliest time at which some invite will have expired.
+    pub fn next_expiration(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .expirations
+            .first()?
+            .and_then(|(key, _)| key.get(..8)?.try_into().ok())
+            .map(|expires| u64::from_be_bytes(expires) + 1))
+    }
+
+    pub async fn flush(&self) -> Result<(), Error> {
+        futures::try_join!(self.invites.flush_async(), self.expirations.flush_async())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper::StatusCode;
+use tokio::io::AsyncWrite;
+
+/// How often, in bytes written, an upload re-checks the free space on disk.
+const FREE_SPACE_INTERVAL: u64 = 8 << 20;
+
+/// Storage limits enforced on uploads.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Largest accepted paste body, in bytes.
+    pub max_paste_size: u64,
+    /// Cap on the combined size of every stored paste, in bytes.
+    pub max_total_size: Option<u64>,
+    /// Uploads are refused once less than this many bytes would remain free on disk.
+    pub min_free_space: u64,
+}
+
+#[derive(Debug)]
+pub enum LimitExceeded {
+    PasteSize(u64),
+    TotalSize(u64),
+    FreeSpace(u64),
+}
+
+impl LimitExceeded {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitExceeded::PasteSize(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            LimitExceeded::TotalSize(_) | LimitExceeded::FreeSpace(_) => {
+                StatusCode::INSUFFICIENT_STORAGE
+            }
+        }
+    }
+
+    /// Finds the limit behind an error returned by [`LimitWriter`] or [`Limits`].
+    pub fn from_io(e: &std::io::Error) -> Option<&LimitExceeded> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LimitExceeded::PasteSize(max) => {
+                write!(f, "pastes may be at most {} bytes", max)
+            }
+            LimitExceeded::TotalSize(max) => {
+                write!(f, "storage quota of {} bytes is exhausted", max)
+            }
+            LimitExceeded::FreeSpace(min) => {
+                write!(f, "less than {} bytes of disk space would remain", min)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+fn exceeded(limit: LimitExceeded) -> std::io::Error {
+    std::io::Error::other(limit)
+}
+
+impl Limits {
+    /// Checks that a paste of `size` bytes fits alongside `used` bytes of other pastes.
+    pub fn check_size(&self, used: u64, size: u64) -> std::io::Result<()> {
+        if size > self.max_paste_size {
+            return Err(exceeded(LimitExceeded::PasteSize(self.max_paste_size)));
+        }
+        match self.max_total_size {
+            Some(max) if used.saturating_add(size) > max => {
+                Err(exceeded(LimitExceeded::TotalSize(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that writing `incoming` more bytes leaves the floor of free space on the disk
+    /// holding the working directory.
+    pub fn check_free_space(&self, incoming: u64) -> std::io::Result<()> {
+        if fs2::available_space(Path::new("."))? < self.min_free_space.saturating_add(incoming) {
+            return Err(exceeded(LimitExceeded::FreeSpace(self.min_free_space)));
+        }
+        Ok(())
+    }
+}
+
+/// Fails writes with a [`LimitExceeded`] as soon as the bytes written break a limit, so an
+/// oversized upload is cut off mid-stream instead of after it lands on disk.
+pub struct LimitWriter<W: AsyncWrite> {
+    writer: W,
+    limits: Limits,
+    used: u64,
+    written: u64,
+    next_free_space_check: u64,
+}
+
+impl<W: AsyncWrite> LimitWriter<W> {
+    /// `used` is the size of every other stored paste when the upload starts.
+    pub fn new(writer: W, limits: Limits, used: u64) -> Self {
+        LimitWriter {
+            writer,
+            limits,
+            used,
+            written: 0,
+            next_free_space_check: 0,
+        }
+    }
+
+    /// Bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for LimitWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<tokio::io::Result<usize>> {
+        let s = self.get_mut();
+        let size = s.written + buf.len() as u64;
+        if let Err(e) = s.limits.check_size(s.used, size) {
+            return Poll::Ready(Err(e));
+        }
+        if size > s.next_free_space_check {
+            if let Err(e) = s.limits.check_free_space(FREE_SPACE_INTERVAL) {
+                return Poll::Ready(Err(e));
+            }
+            s.next_free_space_check = size + FREE_SPACE_INTERVAL;
+        }
+        match AsyncWrite::poll_write(Pin::new(&mut s.writer), cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                s.written += n as u64;
+                Poll::Ready(Ok(n))
+            }
+            a => a,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<tokio::io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.get_mut().writer), cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<tokio::io::Result<()>> {
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.get_mut().writer), cx)
+    }
+}
@@ -1,17 +1,15 @@
-use std::convert::TryInto;
+use std::collections::HashMap;
 use std::future::Future;
 use std::marker::Unpin;
-use std::path::Path;
-use std::pin::Pin;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::task::{Context, Poll};
+use std::task::Poll;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Error as AnyError};
 use async_compat::CompatExt;
 use cookie::Cookie;
-use futures::{Stream, TryFutureExt, TryStreamExt};
-use generic_array::GenericArray;
+use futures::{Stream, StreamExt, TryFutureExt, TryStreamExt};
 use http::response::Builder as ResponseBuilder;
 use hyper::{
     body::{Buf, Bytes},
@@ -21,15 +19,58 @@ use hyper::{
 use lazy_static::lazy_static;
 use sha2::{Digest, Sha256};
 use slog::Drain;
-use tokio::io::{AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::io::{AsyncWriteExt, ReadBuf};
+use tokio::sync::{broadcast, Notify};
 use warp::Filter;
 use web_static_pack::{
     hyper_loader::{Responder, ResponderError},
     loader::Loader,
 };
 
+mod archive;
+mod download;
+mod inbox;
+mod invite;
+mod limits;
+mod paste;
+mod receipt;
+mod session;
+mod store;
+mod token;
+mod upload;
+mod user;
+mod webhook;
+
+use download::{Begun, ByteRange, Downloads};
+use inbox::{Inbox, Refusal, StampError};
+use invite::{Invite, Invites};
+use limits::{LimitExceeded, LimitWriter, Limits};
+use paste::{BundleEntry, InboxMessage, Lifetimes, Passphrase, PasteRecord, Storage, View};
+use receipt::{Outcome, Receipt, Receipts};
+use session::Sessions;
+use store::{Durability, PasteStore, StorageBackend, Store};
+use token::{ApiToken, ApiTokens, Scope};
+use upload::{Upload, Uploads};
+use user::{Role, User, Users};
+use webhook::Webhooks;
+
+const MIB: u64 = 1 << 20;
 const HOUR: Duration = Duration::from_secs(60 * 60);
 const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+/// How long an unfinished resumable upload is kept.
+const UPLOAD_LIFETIME: Duration = DAY;
+/// Longest accepted `x-paste-label`, in bytes.
+const MAX_LABEL_LENGTH: usize = 256;
+/// How long an invite lasts unless it asks otherwise.
+const INVITE_LIFETIME: Duration = DAY;
+/// How often the free space on disk is checked against the low-disk warning.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Most files a bundle may hold.
+const MAX_BUNDLE_FILES: usize = 1000;
+/// Longest accepted name of a file in a bundle, in bytes: as much as a tar header holds.
+const MAX_FILE_NAME_LENGTH: usize = 100;
+/// Content type of every bundle, whatever its files are.
+const BUNDLE_CONTENT_TYPE: &str = "multipart/mixed";
 
 lazy_static! {
     static ref PACK: &'static [u8] = std::include_bytes!("ui.pack");
@@ -136,6 +177,27 @@ fn bad_request<E: std::fmt::Display>(e: E) -> Response<Body> {
         .unwrap()
 }
 
+/// 425 Too Early, for a paste still under embargo.
+fn not_yet_available(not_before: u64, now: u64) -> Response<Body> {
+    base_res()
+        .status(StatusCode::from_u16(425).unwrap())
+        .header(header::RETRY_AFTER, not_before - now)
+        .header("x-paste-not-before", not_before)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body("paste is not available yet".into())
+        .unwrap()
+}
+
+/// 429 Too Many Requests, for a sender who has to wait `retry_after` seconds.
+fn too_many_requests(retry_after: u64) -> Response<Body> {
+    base_res()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, retry_after)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body("too many messages, try again later".into())
+        .unwrap()
+}
+
 fn unauthorized() -> Response<Body> {
     base_res()
         .status(StatusCode::UNAUTHORIZED)
@@ -210,7 +272,7 @@ async fn failable<F: FnOnce() -> Fut, Fut: Future<Output = Result<Response<Body>
 }
 
 async fn authenticate<T, F: FnOnce(String) -> Fut, Fut: Future<Output = Result<T, Error>>>(
-    sesh_tree: sled::Tree,
+    sesh_tree: Sessions,
     session: String,
     f: F,
 ) -> Result<T, Error> {
@@ -218,15 +280,13 @@ async fn authenticate<T, F: FnOnce(String) -> Fut, Fut: Future<Output = Result<T
         .with_status(StatusCode::BAD_REQUEST)
         .with_message(|| anyhow!("parsing session cookie"))?;
     let expiration = sesh_tree
-        .get(&data)?
+        .expiration(&data)?
         .ok_or(Error::Status(StatusCode::UNAUTHORIZED))?;
-    let mut exp = [0; 8];
-    exp.clone_from_slice(&expiration);
     if SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs()
-        > u64::from_be_bytes(exp)
+        > expiration
     {
         sesh_tree.remove(&data)?;
         return Err(Error::StatusWithMessage(
@@ -237,23 +297,69 @@ async fn authenticate<T, F: FnOnce(String) -> Fut, Fut: Future<Output = Result<T
     f(std::str::from_utf8(&data[16..])?.to_owned()).await
 }
 
+/// Like [`authenticate`], for what only admins may do.
+async fn authenticate_admin<T, F: FnOnce(String) -> Fut, Fut: Future<Output = Result<T, Error>>>(
+    sesh_tree: Sessions,
+    users: Users,
+    session: String,
+    f: F,
+) -> Result<T, Error> {
+    authenticate(sesh_tree, session, move |user| async move {
+        if !users.is_admin(&user)? {
+            return Err(Error::StatusWithMessage(
+                StatusCode::FORBIDDEN,
+                anyhow!("only admins may do that"),
+            ));
+        }
+        f(user).await
+    })
+    .await
+}
+
+/// Like [`authenticate`], for an `Authorization: Bearer` API token instead of a session. `f` is
+/// given the user the token acts for, and its scope.
+async fn authenticate_token<
+    T,
+    F: FnOnce(String, Scope) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+>(
+    api_tokens: ApiTokens,
+    users: Users,
+    authorization: String,
+    f: F,
+) -> Result<T, Error> {
+    let token = authorization.strip_prefix("Bearer ").ok_or_else(|| {
+        Error::StatusWithMessage(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("Authorization must be a bearer token"),
+        )
+    })?;
+    let token_hash = Sha256::digest(token.trim().as_bytes()).to_vec();
+    let api_token = api_tokens
+        .get(&token_hash, paste::now())?
+        .ok_or(Error::Status(StatusCode::UNAUTHORIZED))?;
+    // tokens of disabled users stop working until they are enabled again
+    if users.get(&api_token.user)?.is_none_or(|user| user.disabled) {
+        return Err(Error::Status(StatusCode::UNAUTHORIZED));
+    }
+    f(api_token.user, api_token.scope).await
+}
+
 #[derive(serde::Deserialize)]
 struct Login {
     user: String,
     password: String,
 }
 
-async fn login(
-    pwd: Arc<String>,
-    sesh_tree: sled::Tree,
-    login: Login,
-) -> Result<Response<Body>, Error> {
-    if login.user == "admin" && &*pwd == &login.password {
+async fn login(users: Users, sesh_tree: Sessions, login: Login) -> Result<Response<Body>, Error> {
+    let user =
+        tokio::task::spawn_blocking(move || users.login(&login.user, &login.password)).await??;
+    if let Some(user) = user {
         let mut session = vec![0; 16];
         rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut session);
-        session.extend_from_slice(login.user.as_bytes());
+        session.extend_from_slice(user.name.as_bytes());
         let exp = SystemTime::now().duration_since(UNIX_EPOCH)? + (DAY * 7);
-        sesh_tree.insert(&session, &u64::to_be_bytes(exp.as_secs()))?;
+        sesh_tree.insert(&session, exp.as_secs())?;
         let cookie = Cookie::build("session", base64::encode(&session))
             .expires(time::OffsetDateTime::from_unix_timestamp(
                 exp.as_secs() as i64
@@ -272,126 +378,523 @@ async fn login(
     }
 }
 
-async fn logout(sesh_tree: sled::Tree, session: String) -> Result<Response<Body>, Error> {
+async fn logout(sesh_tree: Sessions, session: String) -> Result<Response<Body>, Error> {
     let data = base64::decode(session)
         .with_status(StatusCode::BAD_REQUEST)
         .with_message(|| anyhow!("parsing session cookie"))?;
     sesh_tree.remove(&data)?;
-    sesh_tree.flush_async().await?;
+    sesh_tree.flush().await?;
+    Ok(no_content())
+}
+
+const MAX_USER_NAME_LENGTH: usize = 64;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// An account as shown to admins.
+#[derive(serde::Serialize)]
+struct Account {
+    name: String,
+    role: Role,
+    disabled: bool,
+    created_at: u64,
+    created_by: Option<String>,
+}
+
+impl From<User> for Account {
+    fn from(user: User) -> Self {
+        Account {
+            name: user.name,
+            role: user.role,
+            disabled: user.disabled,
+            created_at: user.created_at,
+            created_by: user.created_by,
+        }
+    }
+}
+
+/// Names end up in session ids, paste records and logs, so they are kept plain.
+fn check_user_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.len() > MAX_USER_NAME_LENGTH
+        || !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+    {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "name must be 1 to {} letters, digits, dots, dashes or underscores",
+                MAX_USER_NAME_LENGTH
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn check_password(password: &str) -> Result<(), Error> {
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("password must be at least {} bytes", MIN_PASSWORD_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+/// The built-in admin follows the service config, so it can't be changed here.
+fn check_not_built_in(name: &str) -> Result<(), Error> {
+    if name == user::BUILT_IN_ADMIN {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("{} is managed through the service config", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Every account, in order of name.
+async fn list_users(logger: Arc<slog::Logger>, users: Users) -> Result<Response<Body>, Error> {
+    let accounts: Vec<Account> = users.all()?.into_iter().map(Account::from).collect();
+    slog::info!(
+        logger,
+        "USERS";
+        "status" => 200,
+        "count" => accounts.len(),
+    );
+    Ok(ok_json(&accounts))
+}
+
+#[derive(serde::Deserialize)]
+struct NewUser {
+    name: String,
+    password: String,
+    role: Role,
+}
+
+async fn new_user(
+    logger: Arc<slog::Logger>,
+    users: Users,
+    body: Bytes,
+    admin: String,
+) -> Result<Response<Body>, Error> {
+    let new: NewUser = serde_json::from_slice(&body).with_status(StatusCode::BAD_REQUEST)?;
+    check_user_name(&new.name)?;
+    check_password(&new.password)?;
+    let now = paste::now();
+    let user = tokio::task::spawn_blocking(move || {
+        User::new(new.name, new.role, &new.password, Some(admin), now)
+    })
+    .await??;
+    if !users.create(&user)? {
+        slog::info!(
+            logger,
+            "NEW USER";
+            "status" => 409,
+            "user" => &user.name,
+        );
+        return Err(Error::StatusWithMessage(
+            StatusCode::CONFLICT,
+            anyhow!("{} already exists", user.name),
+        ));
+    }
+    users.flush().await?;
+    slog::info!(
+        logger,
+        "NEW USER";
+        "status" => 200,
+        "user" => &user.name,
+        "role" => ?user.role,
+        "created-by" => &user.created_by,
+    );
+    Ok(ok_json(&Account::from(user)))
+}
+
+/// Changes to an account. Whatever is left out stays as it is.
+#[derive(serde::Deserialize)]
+struct UserUpdate {
+    disabled: Option<bool>,
+    role: Option<Role>,
+    password: Option<String>,
+}
+
+/// Updates an account. Disabling it or changing its password ends its sessions.
+async fn update_user(
+    logger: Arc<slog::Logger>,
+    users: Users,
+    sesh_tree: Sessions,
+    name: String,
+    body: Bytes,
+    admin: String,
+) -> Result<Response<Body>, Error> {
+    let update: UserUpdate = serde_json::from_slice(&body).with_status(StatusCode::BAD_REQUEST)?;
+    check_not_built_in(&name)?;
+    let mut user = match users.get(&name)? {
+        Some(user) => user,
+        None => {
+            slog::info!(
+                logger,
+                "UPDATE USER";
+                "status" => 404,
+                "user" => name,
+            );
+            return Err(Error::Status(StatusCode::NOT_FOUND));
+        }
+    };
+    let log_out = update.disabled == Some(true) || update.password.is_some();
+    if let Some(password) = update.password {
+        check_password(&password)?;
+        user.password_hash =
+            tokio::task::spawn_blocking(move || paste::hash_secret(&password)).await??;
+    }
+    if let Some(role) = update.role {
+        user.role = role;
+    }
+    if let Some(disabled) = update.disabled {
+        user.disabled = disabled;
+    }
+    users.put(&user)?;
+    if log_out {
+        sesh_tree.remove_user(&name)?;
+    }
+    futures::try_join!(users.flush(), sesh_tree.flush())?;
+    slog::info!(
+        logger,
+        "UPDATE USER";
+        "status" => 200,
+        "user" => &user.name,
+        "role" => ?user.role,
+        "disabled" => user.disabled,
+        "by" => admin,
+    );
+    Ok(ok_json(&Account::from(user)))
+}
+
+/// Deletes an account along with its sessions, unused invites and API tokens. Its pastes stay,
+/// still recorded as its own.
+async fn delete_user(
+    logger: Arc<slog::Logger>,
+    users: Users,
+    sesh_tree: Sessions,
+    invites: Invites,
+    api_tokens: ApiTokens,
+    name: String,
+    admin: String,
+) -> Result<Response<Body>, Error> {
+    check_not_built_in(&name)?;
+    if users.remove(&name)?.is_none() {
+        slog::info!(
+            logger,
+            "DELETE USER";
+            "status" => 404,
+            "user" => name,
+        );
+        return Err(Error::Status(StatusCode::NOT_FOUND));
+    }
+    let sessions = sesh_tree.remove_user(&name)?;
+    let made = invites.made_by(&name)?;
+    for (token_hash, _) in &made {
+        invites.remove(token_hash)?;
+    }
+    let revoked = api_tokens.owned_by(&name, paste::now())?;
+    for (token_hash, _) in &revoked {
+        api_tokens.remove(token_hash)?;
+    }
+    futures::try_join!(
+        users.flush(),
+        sesh_tree.flush(),
+        invites.flush(),
+        api_tokens.flush()
+    )?;
+    slog::info!(
+        logger,
+        "DELETE USER";
+        "status" => 204,
+        "user" => name,
+        "sessions" => sessions,
+        "invites" => made.len(),
+        "tokens" => revoked.len(),
+        "by" => admin,
+    );
     Ok(no_content())
 }
 
+/// The options of a new API token.
+#[derive(serde::Deserialize)]
+struct NewApiToken {
+    name: Option<String>,
+    /// Seconds until the token stops working. Without one it works until revoked.
+    ttl: Option<u64>,
+    #[serde(default = "default_create_only")]
+    create_only: bool,
+    max_size: Option<u64>,
+    max_lifetime: Option<u64>,
+}
+
+fn default_create_only() -> bool {
+    true
+}
+
+#[derive(serde::Serialize)]
+struct NewApiTokenRes {
+    /// Sent as `Authorization: Bearer {token}`. Only its hash is kept.
+    token: String,
+    #[serde(flatten)]
+    api_token: ApiToken,
+}
+
+async fn new_api_token(
+    logger: Arc<slog::Logger>,
+    api_tokens: ApiTokens,
+    policy: PastePolicy,
+    body: Bytes,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    let new: NewApiToken = serde_json::from_slice(&body).with_status(StatusCode::BAD_REQUEST)?;
+    if new
+        .max_size
+        .is_some_and(|max_size| max_size == 0 || max_size > policy.limits.max_paste_size)
+    {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "max_size must be between 1 and {}",
+                policy.limits.max_paste_size
+            ),
+        ));
+    }
+    if new.max_lifetime.is_some_and(|max_lifetime| {
+        max_lifetime < policy.lifetimes.min || max_lifetime > policy.lifetimes.max
+    }) {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "max_lifetime must be between {} and {}",
+                policy.lifetimes.min,
+                policy.lifetimes.max
+            ),
+        ));
+    }
+    if new.ttl == Some(0) {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("ttl must be at least 1"),
+        ));
+    }
+    if new.name.as_ref().map_or(0, String::len) > MAX_LABEL_LENGTH {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("name may be at most {} bytes", MAX_LABEL_LENGTH),
+        ));
+    }
+    let now = paste::now();
+    let api_token = ApiToken {
+        id: webhook::new_id(),
+        user,
+        name: new.name,
+        created_at: now,
+        expires: new.ttl.map(|ttl| now.saturating_add(ttl)),
+        scope: Scope {
+            create_only: new.create_only,
+            max_size: new.max_size,
+            max_lifetime: new.max_lifetime,
+        },
+    };
+    let (token, token_hash) = new_token();
+    api_tokens.insert(&token_hash, &api_token)?;
+    api_tokens.flush().await?;
+    slog::info!(
+        logger,
+        "NEW TOKEN";
+        "status" => 200,
+        "token" => &api_token.id,
+        "user" => &api_token.user,
+        "expires" => api_token.expires,
+        "create-only" => api_token.scope.create_only,
+    );
+    Ok(ok_json(&NewApiTokenRes { token, api_token }))
+}
+
+/// The live API tokens of `user`, oldest first.
+async fn list_api_tokens(
+    logger: Arc<slog::Logger>,
+    api_tokens: ApiTokens,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    let api_tokens: Vec<ApiToken> = api_tokens
+        .owned_by(&user, paste::now())?
+        .into_iter()
+        .map(|(_, api_token)| api_token)
+        .collect();
+    slog::info!(
+        logger,
+        "TOKENS";
+        "status" => 200,
+        "count" => api_tokens.len(),
+    );
+    Ok(ok_json(&api_tokens))
+}
+
+async fn revoke_api_token(
+    logger: Arc<slog::Logger>,
+    api_tokens: ApiTokens,
+    user: String,
+    id: String,
+) -> Result<Response<Body>, Error> {
+    let token_hash = api_tokens
+        .owned_by(&user, paste::now())?
+        .into_iter()
+        .find(|(_, api_token)| api_token.id == id)
+        .map(|(token_hash, _)| token_hash);
+    match token_hash {
+        Some(token_hash) => {
+            api_tokens.remove(&token_hash)?;
+            api_tokens.flush().await?;
+            slog::info!(
+                logger,
+                "REVOKE TOKEN";
+                "status" => 204,
+                "token" => id,
+            );
+            Ok(no_content())
+        }
+        None => {
+            slog::info!(
+                logger,
+                "REVOKE TOKEN";
+                "status" => 404,
+                "token" => id,
+            );
+            Err(Error::Status(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
 async fn data(
     logger: Arc<slog::Logger>,
-    data_tree: sled::Tree,
-    content_type_tree: sled::Tree,
-    expiration_tree: sled::Tree,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
     key: String,
     method: Method,
+    read: ReadRequest,
 ) -> Result<Response<Body>, Error> {
     match method {
-        Method::GET => match (
-            data_tree.get(&key)?,
-            content_type_tree.get(&key)?,
-            expiration_tree.get(&key)?,
-        ) {
-            (Some(data), Some(content_type), Some(expiration))
-                if SystemTime::now()
-                    < (UNIX_EPOCH
-                        + Duration::from_secs(u64::from_be_bytes(
-                            expiration.as_ref().try_into()?,
-                        ))) =>
-            {
-                if data.is_empty() {
-                    let mut file = tokio::fs::File::open(Path::new("big").join(&key)).await?;
-                    let len = file.metadata().await?.len();
-                    let stream: Box<
-                        dyn Stream<
-                            Item = Result<
-                                Bytes,
-                                Box<dyn std::error::Error + 'static + Sync + Send>,
-                            >,
-                        >
-                            + 'static
-                            + Send,
-                    > = Box::new(futures::stream::poll_fn(move |cx| {
-                        let mut buf_inner = [0; 1 << 20];
-                        let mut buf = ReadBuf::new(&mut buf_inner);
-                        match tokio::io::AsyncRead::poll_read(
-                            std::pin::Pin::new(&mut file),
-                            cx,
-                            &mut buf,
-                        ) {
-                            Poll::Ready(Ok(_n)) => {
-                                if buf.filled().is_empty() {
-                                    Poll::Ready(None)
-                                } else {
-                                    Poll::Ready(Some(Ok(Bytes::from(buf.filled().to_vec()))))
-                                }
-                            }
-                            Poll::Ready(Err(e)) => {
-                                Poll::Ready(Some(Err::<
-                                    _,
-                                    Box<dyn std::error::Error + 'static + Sync + Send>,
-                                >(Box::new(e))))
-                            }
-                            Poll::Pending => Poll::Pending,
-                        }
-                    }));
+        Method::GET => {
+            let now = paste::now();
+            match pastes.get(&key)? {
+                // inbox messages are only served through the inbox
+                Some(record) if record.inbox.is_some() => {
                     slog::info!(
                         logger,
                         "GET";
-                        "status" => 200,
+                        "status" => 404,
                         "key" => key,
-                        "content-type" => std::str::from_utf8(content_type.as_ref())?,
-                        "content-length" => len,
                     );
-                    Ok(ok()
-                        .header(header::CONTENT_TYPE, content_type.to_vec())
-                        .header(header::CONTENT_LENGTH, len)
-                        .body(stream.into())
-                        .unwrap())
-                } else {
+                    return Err(Error::Status(StatusCode::NOT_FOUND));
+                }
+                Some(record) if record.bundle.is_some() && !record.is_expired(now) => {
+                    slog::info!(
+                        logger,
+                        "GET";
+                        "status" => 303,
+                        "key" => &key,
+                    );
+                    return Ok(base_res()
+                        .status(StatusCode::SEE_OTHER)
+                        .header(header::LOCATION, format!("/api/bundles/{}", key))
+                        .body(Body::empty())
+                        .unwrap());
+                }
+                Some(record) if record.is_embargoed(now) => {
+                    slog::info!(
+                        logger,
+                        "GET";
+                        "status" => 425,
+                        "key" => key,
+                    );
+                    return Ok(not_yet_available(record.not_before.unwrap_or(now), now));
+                }
+                Some(record) if !record.is_expired(now) => {
+                    unlock(
+                        &logger,
+                        &*pastes,
+                        &receipts,
+                        &downloads,
+                        &key,
+                        &record,
+                        read.passphrase.as_deref(),
+                    )
+                    .await?;
+                    if record.storage == Storage::Big {
+                        return download(logger, pastes, downloads, key, None, read, now).await;
+                    }
+                }
+                _ => (),
+            }
+            match pastes.view(&key, now).await? {
+                View::Live {
+                    record,
+                    body: Some(data),
+                } => {
+                    pastes.flush().await?;
+                    receipts.viewed(&key, &record, now);
                     slog::info!(
                         logger,
                         "GET";
                         "status" => 200,
                         "key" => key,
-                        "content-type" => std::str::from_utf8(content_type.as_ref())?,
+                        "content-type" => &record.content_type,
                         "content-length" => data.len(),
+                        "remaining-views" => record.remaining_views,
                     );
                     Ok(ok()
-                        .header(header::CONTENT_TYPE, content_type.to_vec())
+                        .header(header::CONTENT_TYPE, record.content_type)
                         .header(header::CONTENT_LENGTH, data.len())
                         .body(data.to_vec().into())
                         .unwrap())
                 }
+                View::Live { .. } => {
+                    Err(anyhow!("big paste {} consumed without download", key).into())
+                }
+                expired => {
+                    if let View::Expired(record) = expired {
+                        if record.storage == Storage::Big {
+                            downloads.remove_blob(&key).await?;
+                        }
+                        pastes.flush().await?;
+                        receipts.ended(&key, &record, Outcome::Expired, now);
+                    }
+                    slog::info!(
+                        logger,
+                        "GET";
+                        "status" => 404,
+                        "key" => key,
+                    );
+                    Err(Error::Status(StatusCode::NOT_FOUND))
+                }
             }
-            _ => {
-                slog::info!(
-                    logger,
-                    "GET";
-                    "status" => 404,
-                    "key" => key,
-                );
-                Err(Error::Status(StatusCode::NOT_FOUND))
-            }
-        },
+        }
+        Method::HEAD => head(logger, &*pastes, key).await,
         Method::DELETE => {
-            content_type_tree.remove(&key)?;
-            let data = data_tree.remove(&key)?;
-            expiration_tree.remove(&key)?;
-            let rm = if data.map(|d| d.len()) == Some(0) {
-                futures::future::Either::Left(tokio::fs::remove_file(Path::new("big").join(&key)))
-            } else {
-                futures::future::Either::Right(async { Ok(()) })
+            let record = pastes.take(&key)?;
+            let rm = async {
+                if record.as_ref().map(|r| r.storage) == Some(Storage::Big) {
+                    downloads.remove_blob(&key).await?;
+                }
+                Ok(())
             };
-            futures::try_join!(
-                data_tree.flush_async().map_err(Error::from),
-                content_type_tree.flush_async().map_err(Error::from),
-                expiration_tree.flush_async().map_err(Error::from),
-                rm.map_err(Error::from),
-            )?;
+            futures::try_join!(pastes.flush(), rm)?;
+            if let Some(record) = record {
+                let now = paste::now();
+                // the expiration cleaner deletes pastes too
+                let outcome = if record.is_expired(now) {
+                    Outcome::Expired
+                } else {
+                    Outcome::Deleted
+                };
+                receipts.ended(&key, &record, outcome, now);
+            }
             slog::info!(
                 logger,
                 "DELETE";
@@ -404,156 +907,2833 @@ async fn data(
     }
 }
 
-async fn new_data_small(
+/// Deletes a paste on behalf of `user`, who must own it or be an admin.
+async fn delete_paste(
     logger: Arc<slog::Logger>,
-    data_tree: sled::Tree,
-    content_type_tree: sled::Tree,
-    expiration_tree: sled::Tree,
-    content_type: String,
-    expiration: u64,
-    data: Bytes,
-) -> Result<String, Error> {
-    if data.is_empty() {
-        return Err(Error::StatusWithMessage(
-            StatusCode::BAD_REQUEST,
-            anyhow!("body required"),
-        ));
+    users: Users,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
+    key: String,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    if let Some(record) = pastes.get(&key)? {
+        if record.owner.as_deref() != Some(&*user) && !users.is_admin(&user)? {
+            slog::info!(
+                logger,
+                "DELETE";
+                "status" => 403,
+                "key" => key,
+            );
+            return Err(Error::StatusWithMessage(
+                StatusCode::FORBIDDEN,
+                anyhow!("only admins may delete pastes of others"),
+            ));
+        }
     }
-    let mut hasher = Sha256::new();
-    hasher.update(&*data);
-    let key = base64::encode_config(
-        &hasher.finalize(),
-        base64::Config::new(base64::CharacterSet::UrlSafe, true),
-    );
-    data_tree.insert(&key, &*data)?;
-    content_type_tree.insert(&key, content_type.as_bytes())?;
-    expiration_tree.insert(&key, &u64::to_be_bytes(expiration))?;
-    slog::info!(
+    // boxed, since the whole of data's future is too much for the stack in debug builds
+    Box::pin(data(
         logger,
-        "CREATE";
-        "status" => 200,
-        "key" => &key,
-        "content-type" => content_type,
-        "content-length" => data.len(),
-        "expiration" => %time::OffsetDateTime::from_unix_timestamp(expiration as i64)?,
-    );
-    Ok(key)
+        pastes,
+        receipts,
+        downloads,
+        key,
+        Method::DELETE,
+        ReadRequest::default(),
+    ))
+    .await
 }
 
-struct HashWriter<D: Digest, W: AsyncWrite> {
-    hasher: D,
-    writer: W,
-}
-impl<D, W> HashWriter<D, W>
-where
-    D: Digest,
-    W: AsyncWrite,
-{
-    fn new(w: W) -> Self {
-        HashWriter {
-            hasher: D::new(),
-            writer: w,
+/// Checks the passphrase given for a paste that has one. Wrong guesses are counted, and the one
+/// that uses up the paste's attempts burns it.
+async fn unlock(
+    logger: &slog::Logger,
+    pastes: &dyn PasteStore,
+    receipts: &Receipts,
+    downloads: &Downloads,
+    key: &str,
+    record: &PasteRecord,
+    guess: Option<&str>,
+) -> Result<(), Error> {
+    let passphrase = match &record.passphrase {
+        Some(passphrase) => passphrase.clone(),
+        None => return Ok(()),
+    };
+    let guess = match guess {
+        Some(guess) => guess.to_owned(),
+        None => {
+            return Err(Error::StatusWithMessage(
+                StatusCode::UNAUTHORIZED,
+                anyhow!("x-paste-passphrase required"),
+            ))
         }
+    };
+    if tokio::task::spawn_blocking(move || passphrase.verify(&guess)).await?? {
+        return Ok(());
     }
-}
-impl<D, W> HashWriter<D, W>
-where
-    D: Digest,
-    W: AsyncWrite + Unpin,
-{
-    async fn finish(mut self) -> tokio::io::Result<GenericArray<u8, D::OutputSize>> {
-        self.writer.flush().await?;
-        self.writer.shutdown().await?;
-        Ok(self.hasher.finalize())
-    }
-}
-impl<D, W> AsyncWrite for HashWriter<D, W>
-where
-    D: Digest + Unpin,
-    W: AsyncWrite + Unpin,
-{
-    fn poll_write(
-        self: Pin<&mut Self>,
-        cx: &mut Context,
-        buf: &[u8],
-    ) -> Poll<tokio::io::Result<usize>> {
-        let s = self.get_mut();
-        match AsyncWrite::poll_write(Pin::new(&mut s.writer), cx, buf) {
-            Poll::Ready(Ok(n)) => {
-                let hasher = &mut s.hasher;
-                hasher.update(&buf[0..n]);
-                Poll::Ready(Ok(n))
+    let failed_attempts = pastes
+        .update(key, &|record| {
+            if let Some(passphrase) = &mut record.passphrase {
+                passphrase.failed_attempts += 1;
             }
-            a => a,
+        })?
+        .and_then(|record| record.passphrase)
+        .map(|passphrase| (passphrase.failed_attempts, passphrase.max_attempts));
+    let locked_out = match failed_attempts {
+        Some((failed, max)) if failed >= max => pastes.take(key)?,
+        _ => None,
+    };
+    if let Some(record) = &locked_out {
+        if record.storage == Storage::Big {
+            downloads.remove_blob(key).await?;
         }
     }
-
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<tokio::io::Result<()>> {
-        AsyncWrite::poll_flush(unsafe { self.map_unchecked_mut(|s| &mut s.writer) }, cx)
-    }
-
-    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<tokio::io::Result<()>> {
-        AsyncWrite::poll_shutdown(unsafe { self.map_unchecked_mut(|s| &mut s.writer) }, cx)
+    // flushed either way, so a crash can't hand back an attempt
+    pastes.flush().await?;
+    if let Some(record) = &locked_out {
+        receipts.ended(key, record, Outcome::LockedOut, paste::now());
     }
+    slog::info!(
+        logger,
+        "UNLOCK";
+        "status" => 403,
+        "key" => key,
+        "failed-attempts" => failed_attempts.map(|(failed, _)| failed),
+        "locked-out" => locked_out.is_some(),
+    );
+    Err(Error::StatusWithMessage(
+        StatusCode::FORBIDDEN,
+        if locked_out.is_some() {
+            anyhow!("wrong passphrase, and the paste has burned")
+        } else {
+            anyhow!("wrong passphrase")
+        },
+    ))
 }
 
-async fn new_data<S: Stream<Item = Result<B, warp::Error>> + Unpin, B: Buf>(
-    logger: Arc<slog::Logger>,
-    data_tree: sled::Tree,
-    content_type_tree: sled::Tree,
-    expiration_tree: sled::Tree,
+/// What a recipient may learn about a paste without reading it.
+#[derive(serde::Serialize)]
+struct PasteMeta {
+    size: u64,
+    content_type: String,
+    expiration: u64,
+    remaining_views: u64,
+    password_protected: bool,
+    not_before: Option<u64>,
+    passphrase_required: bool,
+    /// Wrong passphrases guessed so far, and how many the paste allows.
+    failed_attempts: u32,
+    max_attempts: Option<u32>,
+}
+
+/// Looks up a paste without consuming a view, returning its record and whether its body is
+/// password-protected. Expired pastes are treated as missing but left to the cleaner, and inbox
+/// messages as missing altogether.
+async fn inspect(pastes: &dyn PasteStore, key: &str) -> Result<Option<(PasteRecord, bool)>, Error> {
+    let record = match pastes.get(key)? {
+        Some(record) if !record.is_expired(paste::now()) && record.inbox.is_none() => record,
+        _ => return Ok(None),
+    };
+    let header = match record.storage {
+        Storage::Inline => pastes
+            .peek(key, paste::PASSWORD_HEADER_LEN)?
+            .unwrap_or_default(),
+        // the files of a bundle are stored as uploaded, without the frontend's header
+        Storage::Big if record.bundle.is_some() => Bytes::new(),
+        Storage::Big => match pastes.blobs().open(key).await? {
+            Some((_, blob)) => {
+                let mut header = Vec::with_capacity(paste::PASSWORD_HEADER_LEN);
+                tokio::io::AsyncReadExt::read_to_end(
+                    &mut tokio::io::AsyncReadExt::take(blob, paste::PASSWORD_HEADER_LEN as u64),
+                    &mut header,
+                )
+                .await?;
+                header.into()
+            }
+            None => return Ok(None),
+        },
+    };
+    Ok(Some((record, paste::password_protected(&header))))
+}
+
+async fn head(
+    logger: Arc<slog::Logger>,
+    pastes: &dyn PasteStore,
+    key: String,
+) -> Result<Response<Body>, Error> {
+    let (record, password_protected) = match inspect(pastes, &key).await? {
+        Some(paste) => paste,
+        None => {
+            slog::info!(
+                logger,
+                "HEAD";
+                "status" => 404,
+                "key" => key,
+            );
+            return Err(Error::Status(StatusCode::NOT_FOUND));
+        }
+    };
+    slog::info!(
+        logger,
+        "HEAD";
+        "status" => 200,
+        "key" => key,
+    );
+    let mut res = ok()
+        .header(header::CONTENT_TYPE, &record.content_type)
+        .header(header::CONTENT_LENGTH, record.size)
+        .header("x-paste-expiration", record.expiration)
+        .header("x-paste-remaining-views", record.remaining_views)
+        .header("x-paste-password-protected", password_protected.to_string());
+    if let Some(not_before) = record.not_before {
+        res = res.header("x-paste-not-before", not_before);
+    }
+    if let Some(passphrase) = &record.passphrase {
+        res = res
+            .header("x-paste-failed-attempts", passphrase.failed_attempts)
+            .header("x-paste-max-attempts", passphrase.max_attempts);
+    }
+    if record.storage == Storage::Big {
+        res = res
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::ETAG, etag(&record));
+    }
+    Ok(res.body(Body::empty()).unwrap())
+}
+
+async fn meta(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    key: String,
+) -> Result<Response<Body>, Error> {
+    let (record, password_protected) = match inspect(&*pastes, &key).await? {
+        Some(paste) => paste,
+        None => {
+            slog::info!(
+                logger,
+                "META";
+                "status" => 404,
+                "key" => key,
+            );
+            return Err(Error::Status(StatusCode::NOT_FOUND));
+        }
+    };
+    slog::info!(
+        logger,
+        "META";
+        "status" => 200,
+        "key" => key,
+    );
+    Ok(ok_json(&PasteMeta {
+        size: record.size,
+        content_type: record.content_type,
+        expiration: record.expiration,
+        remaining_views: record.remaining_views,
+        password_protected,
+        not_before: record.not_before,
+        passphrase_required: record.passphrase.is_some(),
+        failed_attempts: record
+            .passphrase
+            .as_ref()
+            .map_or(0, |passphrase| passphrase.failed_attempts),
+        max_attempts: record
+            .passphrase
+            .as_ref()
+            .map(|passphrase| passphrase.max_attempts),
+    }))
+}
+
+/// A paste's receipt as shown to its owner, with the views it has left.
+#[derive(serde::Serialize)]
+struct PasteStatus {
+    key: String,
+    #[serde(flatten)]
+    receipt: Receipt,
+    remaining_views: u64,
+}
+
+/// The status of a paste owned by `user`, whether or not it still exists.
+fn paste_status(
+    pastes: &dyn PasteStore,
+    receipts: &Receipts,
+    user: &str,
+    key: String,
+) -> Result<Option<PasteStatus>, Error> {
+    let record = pastes.get(&key)?;
+    let receipt = match receipts.get(&key)? {
+        Some(receipt) => receipt,
+        None => match record.as_ref().and_then(Receipt::new) {
+            Some(receipt) => receipt,
+            None => return Ok(None),
+        },
+    };
+    if receipt.owner != user {
+        return Ok(None);
+    }
+    Ok(Some(PasteStatus {
+        key,
+        receipt,
+        remaining_views: record.map_or(0, |record| record.remaining_views),
+    }))
+}
+
+async fn status(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    user: String,
+    key: String,
+) -> Result<Response<Body>, Error> {
+    match paste_status(&*pastes, &receipts, &user, key.clone())? {
+        Some(status) => {
+            slog::info!(
+                logger,
+                "STATUS";
+                "status" => 200,
+                "key" => key,
+            );
+            Ok(ok_json(&status))
+        }
+        None => {
+            slog::info!(
+                logger,
+                "STATUS";
+                "status" => 404,
+                "key" => key,
+            );
+            Err(Error::Status(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
+/// The status of every paste `user` has created that still exists or has a receipt, newest first.
+async fn statuses(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    let mut statuses: HashMap<String, PasteStatus> = receipts
+        .owned(&user)?
+        .into_iter()
+        .map(|(key, receipt)| {
+            let status = PasteStatus {
+                key: key.clone(),
+                receipt,
+                remaining_views: 0,
+            };
+            (key, status)
+        })
+        .collect();
+    for (key, record) in pastes.records()? {
+        if record.owner.as_deref() != Some(&*user) {
+            continue;
+        }
+        let remaining_views = record.remaining_views;
+        if let Some(receipt) = Receipt::new(&record) {
+            statuses
+                .entry(key.clone())
+                .or_insert(PasteStatus {
+                    key,
+                    receipt,
+                    remaining_views,
+                })
+                .remaining_views = remaining_views;
+        }
+    }
+    let mut statuses: Vec<_> = statuses.into_values().collect();
+    statuses.sort_by_key(|status| std::cmp::Reverse(status.receipt.created_at));
+    slog::info!(
+        logger,
+        "STATUS";
+        "status" => 200,
+        "count" => statuses.len(),
+    );
+    Ok(ok_json(&statuses))
+}
+
+/// Streams changes to the receipts of `user` as Server-Sent Events, named after the change and
+/// carrying the paste status.
+async fn events(
+    logger: Arc<slog::Logger>,
+    receipts: Receipts,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    slog::info!(
+        logger,
+        "EVENTS";
+        "status" => 200,
+        "user" => &user,
+    );
+    let stream = futures::stream::unfold(receipts.subscribe(), |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => return Some((event, events)),
+                // a subscriber that fell behind only misses what it was too slow for
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+    .filter(move |event| futures::future::ready(event.receipt.owner == user))
+    .map(|event| {
+        warp::sse::Event::default()
+            .event(event.receipt.event())
+            .json_data(&event)
+    });
+    Ok(warp::reply::Reply::into_response(warp::sse::reply(
+        warp::sse::keep_alive().stream(stream),
+    )))
+}
+
+fn default_page_size() -> usize {
+    50
+}
+
+/// Most pastes listed at once.
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Filters and paging for the admin listing of active pastes.
+#[derive(serde::Deserialize)]
+struct PasteQuery {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_page_size")]
+    limit: usize,
+    /// Only pastes that expire within this many seconds.
+    expiring_within: Option<u64>,
+    /// Only pastes larger than this many bytes.
+    larger_than: Option<u64>,
+    /// Only pastes whose content type starts with this, so `image/` matches every image.
+    content_type: Option<String>,
+}
+
+impl PasteQuery {
+    fn matches(&self, record: &PasteRecord, now: u64) -> bool {
+        !record.is_expired(now)
+            && self
+                .expiring_within
+                .is_none_or(|within| record.expiration <= now.saturating_add(within))
+            && self.larger_than.is_none_or(|size| record.size > size)
+            && self
+                .content_type
+                .as_ref()
+                .is_none_or(|prefix| record.content_type.starts_with(prefix.as_str()))
+    }
+}
+
+/// An active paste as listed for the admin.
+#[derive(serde::Serialize)]
+struct PasteSummary {
+    key: String,
+    size: u64,
+    content_type: String,
+    created_at: u64,
+    expiration: u64,
+    remaining_views: u64,
+    owner: Option<String>,
+    label: Option<String>,
+    not_before: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct PasteList {
+    /// How many pastes match, across every page.
+    total: usize,
+    pastes: Vec<PasteSummary>,
+}
+
+/// A page of the active pastes matching `query`, newest first.
+async fn list_pastes(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    query: PasteQuery,
+) -> Result<Response<Body>, Error> {
+    let now = paste::now();
+    let mut matching: Vec<_> = pastes
+        .records()?
+        .into_iter()
+        .filter(|(_, record)| query.matches(record, now))
+        .collect();
+    matching.sort_by(|(a_key, a), (b_key, b)| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then_with(|| a_key.cmp(b_key))
+    });
+    let total = matching.len();
+    let page: Vec<_> = matching
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.min(MAX_PAGE_SIZE))
+        .map(|(key, record)| PasteSummary {
+            key,
+            size: record.size,
+            content_type: record.content_type,
+            created_at: record.created_at,
+            expiration: record.expiration,
+            remaining_views: record.remaining_views,
+            owner: record.owner,
+            label: record.label,
+            not_before: record.not_before,
+        })
+        .collect();
+    slog::info!(
+        logger,
+        "LIST";
+        "status" => 200,
+        "total" => total,
+        "count" => page.len(),
+    );
+    Ok(ok_json(&PasteList {
+        total,
+        pastes: page,
+    }))
+}
+
+/// Deletes pastes the way `DELETE /api/data/{key}` does, logging failures under `context`, and
+/// returns how many were deleted.
+async fn delete_pastes(
+    logger: &Arc<slog::Logger>,
+    pastes: &Store,
+    receipts: &Receipts,
+    downloads: &Downloads,
+    keys: Vec<String>,
+    context: &str,
+) -> usize {
+    let mut deleted: usize = 0;
+    for key in keys {
+        match data(
+            logger.clone(),
+            pastes.clone(),
+            receipts.clone(),
+            downloads.clone(),
+            key,
+            Method::DELETE,
+            ReadRequest::default(),
+        )
+        .await
+        {
+            Ok(_) => deleted += 1,
+            Err(e) => slog::error!(
+                logger,
+                "ERROR";
+                "context" => context,
+                "reason" => %e,
+            ),
+        }
+    }
+    deleted
+}
+
+/// A bulk action on active pastes.
+#[derive(serde::Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum BulkAction {
+    /// Takes down the listed pastes.
+    Revoke { keys: Vec<String> },
+    /// Takes down every paste created before the given Unix timestamp.
+    Purge { created_before: u64 },
+}
+
+#[derive(serde::Serialize)]
+struct BulkRes {
+    deleted: usize,
+}
+
+async fn bulk_action(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
+    body: Bytes,
+) -> Result<Response<Body>, Error> {
+    let action: BulkAction = serde_json::from_slice(&body).with_status(StatusCode::BAD_REQUEST)?;
+    let (verb, keys) = match action {
+        BulkAction::Revoke { keys } => {
+            let mut live = Vec::with_capacity(keys.len());
+            for key in keys {
+                // keys that are already gone don't count as deleted
+                if pastes.contains_key(&key)? {
+                    live.push(key);
+                }
+            }
+            ("revoke", live)
+        }
+        BulkAction::Purge { created_before } => (
+            "purge",
+            pastes
+                .records()?
+                .into_iter()
+                .filter(|(_, record)| record.created_at < created_before)
+                .map(|(key, _)| key)
+                .collect(),
+        ),
+    };
+    let deleted = delete_pastes(&logger, &pastes, &receipts, &downloads, keys, "bulk action").await;
+    slog::info!(
+        logger,
+        "BULK";
+        "status" => 200,
+        "action" => verb,
+        "deleted" => deleted,
+    );
+    Ok(ok_json(&BulkRes { deleted }))
+}
+
+/// A strong validator for a big paste, whose body never changes.
+fn etag(record: &PasteRecord) -> String {
+    format!("\"{:x}-{:x}\"", record.created_at, record.size)
+}
+
+fn entry_etag(record: &PasteRecord, index: usize) -> String {
+    format!("\"{:x}-{:x}-{}\"", record.created_at, record.size, index)
+}
+
+/// A `Content-Disposition` that saves the response as `name`.
+fn attachment(name: &str) -> String {
+    let name: String = name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+    format!("attachment; filename*=UTF-8''{}", name)
+}
+
+/// The headers of a read: `Range`, `If-Range` and the token of the download to continue, for a
+/// download, and the passphrase of a paste that has one.
+#[derive(Default)]
+struct ReadRequest {
+    range: Option<String>,
+    if_range: Option<String>,
+    download: Option<String>,
+    passphrase: Option<String>,
+}
+
+fn read_headers() -> impl Filter<Extract = (ReadRequest,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("range")
+        .and(warp::header::optional::<String>("if-range"))
+        .and(warp::header::optional::<String>("x-paste-download"))
+        .and(warp::header::optional::<String>("x-paste-passphrase"))
+        .map(|range, if_range, download, passphrase| ReadRequest {
+            range,
+            if_range,
+            download,
+            passphrase,
+        })
+}
+
+/// Streams all or part of a big paste, or of one file of a bundle. The view is only taken once the
+/// download has delivered every byte, so an interrupted download can be resumed with `Range` and
+/// `If-Range`, along with the `x-paste-download` token handed out when it began. Without the
+/// token, any read while the download runs is refused, so the paste still has one reader per view.
+/// The files of a bundle are read as one download, all with the same token.
+async fn download(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    downloads: Downloads,
+    key: String,
+    entry: Option<usize>,
+    read: ReadRequest,
+    now: u64,
+) -> Result<Response<Body>, Error> {
+    let not_found = || {
+        slog::info!(
+            logger,
+            "GET";
+            "status" => 404,
+            "key" => &key,
+        );
+        Error::Status(StatusCode::NOT_FOUND)
+    };
+    let (record, mut delivery) = match downloads.begin(&key, read.download.as_deref(), now)? {
+        Begun::Delivering(record, delivery) => (record, delivery),
+        Begun::Busy => return Err(download_busy(&logger, &key)),
+        Begun::Missing => return Err(not_found()),
+    };
+    // a download forgotten in a crash could be started over without ever taking a view
+    pastes.flush().await?;
+    let token = delivery.token.take();
+    let (len, mut file) = match pastes.blobs().open(&key).await? {
+        Some(blob) => blob,
+        None => return Err(not_found()),
+    };
+    // ranges are taken within the file asked for, which sits at `offset` in the blob
+    let (offset, size, content_type, etag, disposition) = match entry {
+        None => (0, len, record.content_type.clone(), etag(&record), None),
+        Some(index) => match record.bundle.as_ref().and_then(|bundle| bundle.get(index)) {
+            Some(file) => (
+                file.offset,
+                file.size,
+                file.content_type.clone(),
+                entry_etag(&record, index),
+                Some(attachment(&file.name)),
+            ),
+            None => return Err(not_found()),
+        },
+    };
+    let byte_range = match read.if_range {
+        Some(validator) if validator.trim() != etag => ByteRange::Full,
+        _ => ByteRange::parse(read.range.as_deref(), size),
+    };
+    let (status, part) = match byte_range {
+        ByteRange::Full => (StatusCode::OK, 0..size),
+        ByteRange::Partial(part) => (StatusCode::PARTIAL_CONTENT, part),
+        ByteRange::Unsatisfiable => {
+            slog::info!(
+                logger,
+                "GET";
+                "status" => 416,
+                "key" => key,
+                "range" => read.range,
+            );
+            return Ok(base_res()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .header(header::ETAG, etag)
+                .body(Body::empty())
+                .unwrap());
+        }
+    };
+    if offset + part.start > 0 {
+        tokio::io::AsyncSeekExt::seek(&mut file, std::io::SeekFrom::Start(offset + part.start))
+            .await?;
+    }
+    delivery.len = len;
+    delivery.start = offset + part.start;
+    let mut file = tokio::io::AsyncReadExt::take(file, part.end - part.start);
+    let stream: Box<
+        dyn Stream<Item = Result<Bytes, Box<dyn std::error::Error + 'static + Sync + Send>>>
+            + 'static
+            + Send,
+    > = Box::new(futures::stream::poll_fn(move |cx| {
+        // the whole delivery moves into the stream, to be dropped along with the response
+        let delivery = &mut delivery;
+        let mut buf_inner = [0; 1 << 20];
+        let mut buf = ReadBuf::new(&mut buf_inner);
+        match tokio::io::AsyncRead::poll_read(std::pin::Pin::new(&mut file), cx, &mut buf) {
+            Poll::Ready(Ok(_n)) => {
+                if buf.filled().is_empty() {
+                    Poll::Ready(None)
+                } else {
+                    // counted as delivered once handed to hyper, which is as far as we can see
+                    delivery.sent += buf.filled().len() as u64;
+                    Poll::Ready(Some(Ok(Bytes::from(buf.filled().to_vec()))))
+                }
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Some(Err::<
+                _,
+                Box<dyn std::error::Error + 'static + Sync + Send>,
+            >(Box::new(e)))),
+            Poll::Pending => Poll::Pending,
+        }
+    }));
+    slog::info!(
+        logger,
+        "GET";
+        "status" => status.as_u16(),
+        "key" => key,
+        "entry" => entry,
+        "content-type" => &content_type,
+        "content-length" => part.end - part.start,
+        "range" => read.range,
+        "remaining-views" => record.remaining_views,
+    );
+    let mut res = base_res()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, part.end - part.start)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, etag);
+    if let Some(token) = token {
+        res = res.header("x-paste-download", token);
+    }
+    if let Some(disposition) = disposition {
+        res = res.header(header::CONTENT_DISPOSITION, disposition);
+    }
+    if status == StatusCode::PARTIAL_CONTENT {
+        res = res.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", part.start, part.end - 1, size),
+        );
+    }
+    Ok(res.body(stream.into()).unwrap())
+}
+
+/// What a recipient may learn about a bundle without downloading it.
+#[derive(serde::Serialize)]
+struct BundleListing {
+    expiration: u64,
+    remaining_views: u64,
+    not_before: Option<u64>,
+    files: Vec<BundleFile>,
+}
+
+#[derive(serde::Serialize)]
+struct BundleFile {
+    name: String,
+    content_type: String,
+    size: u64,
+}
+
+/// Lists the files of a bundle without consuming a view. Their names are as private as their
+/// contents, so a bundle's passphrase is needed for this too.
+async fn list_bundle(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
+    key: String,
+    passphrase: Option<String>,
+) -> Result<Response<Body>, Error> {
+    let record = match pastes.get(&key)? {
+        Some(record) if record.bundle.is_some() && !record.is_expired(paste::now()) => record,
+        _ => {
+            slog::info!(
+                logger,
+                "LIST";
+                "status" => 404,
+                "key" => key,
+            );
+            return Err(Error::Status(StatusCode::NOT_FOUND));
+        }
+    };
+    unlock(
+        &logger,
+        &*pastes,
+        &receipts,
+        &downloads,
+        &key,
+        &record,
+        passphrase.as_deref(),
+    )
+    .await?;
+    slog::info!(
+        logger,
+        "LIST";
+        "status" => 200,
+        "key" => key,
+    );
+    Ok(ok_json(&BundleListing {
+        expiration: record.expiration,
+        remaining_views: record.remaining_views,
+        not_before: record.not_before,
+        files: record
+            .bundle
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| BundleFile {
+                name: entry.name,
+                content_type: entry.content_type,
+                size: entry.size,
+            })
+            .collect(),
+    }))
+}
+
+fn bundle_not_found(logger: &slog::Logger, key: &str) -> Error {
+    slog::info!(
+        logger,
+        "GET";
+        "status" => 404,
+        "key" => key,
+    );
+    Error::Status(StatusCode::NOT_FOUND)
+}
+
+/// A read of a big paste that another download is already reading.
+fn download_busy(logger: &slog::Logger, key: &str) -> Error {
+    slog::info!(
+        logger,
+        "GET";
+        "status" => 409,
+        "key" => key,
+    );
+    Error::StatusWithMessage(
+        StatusCode::CONFLICT,
+        anyhow!("the paste is already being downloaded"),
+    )
+}
+
+/// A download from a bundle: one of its files, or all of them as an archive.
+#[derive(Clone, Copy)]
+enum BundlePart {
+    Entry(usize),
+    Archive(archive::Format),
+}
+
+impl std::str::FromStr for BundlePart {
+    type Err = std::num::ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tar" => Ok(BundlePart::Archive(archive::Format::Tar)),
+            "zip" => Ok(BundlePart::Archive(archive::Format::Zip)),
+            index => index.parse().map(BundlePart::Entry),
+        }
+    }
+}
+
+async fn bundle(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
+    key: String,
+    part: BundlePart,
+    read: ReadRequest,
+) -> Result<Response<Body>, Error> {
+    let now = paste::now();
+    let record = match pastes.get(&key)? {
+        Some(record) if record.bundle.is_some() && !record.is_expired(now) => record,
+        _ => return Err(bundle_not_found(&logger, &key)),
+    };
+    if record.is_embargoed(now) {
+        slog::info!(
+            logger,
+            "GET";
+            "status" => 425,
+            "key" => key,
+        );
+        return Ok(not_yet_available(record.not_before.unwrap_or(now), now));
+    }
+    unlock(
+        &logger,
+        &*pastes,
+        &receipts,
+        &downloads,
+        &key,
+        &record,
+        read.passphrase.as_deref(),
+    )
+    .await?;
+    let files = record.bundle.unwrap_or_default();
+    match part {
+        BundlePart::Entry(index) if index < files.len() => {
+            download(logger, pastes, downloads, key, Some(index), read, now).await
+        }
+        BundlePart::Entry(_) => Err(bundle_not_found(&logger, &key)),
+        BundlePart::Archive(format) if format.len(&files).is_none() => {
+            Err(Error::StatusWithMessage(
+                StatusCode::BAD_REQUEST,
+                anyhow!("bundle is too large for zip, download it as tar instead"),
+            ))
+        }
+        BundlePart::Archive(format) => {
+            archive(logger, pastes, downloads, key, format, read, now).await
+        }
+    }
+}
+
+/// Streams every file of a bundle as one archive. An archive can't be resumed, but each file can
+/// still be downloaded on its own to finish the bundle.
+async fn archive(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    downloads: Downloads,
+    key: String,
+    format: archive::Format,
+    read: ReadRequest,
+    now: u64,
+) -> Result<Response<Body>, Error> {
+    let (record, mut delivery) = match downloads.begin(&key, read.download.as_deref(), now)? {
+        Begun::Delivering(record, delivery) => (record, delivery),
+        Begun::Busy => return Err(download_busy(&logger, &key)),
+        Begun::Missing => return Err(bundle_not_found(&logger, &key)),
+    };
+    // a download forgotten in a crash could be started over without ever taking a view
+    pastes.flush().await?;
+    let token = delivery.token.take();
+    let (len, file) = match pastes.blobs().open(&key).await? {
+        Some(blob) => blob,
+        None => return Err(bundle_not_found(&logger, &key)),
+    };
+    let files = record.bundle.unwrap_or_default();
+    let archive_len = format
+        .len(&files)
+        .ok_or_else(|| anyhow!("bundle {} outgrew its archive", key))?;
+    delivery.len = len;
+    let archive = archive::Archive::new(format, files, file, record.created_at);
+    // the delivery moves into the stream, to be dropped along with the response
+    let stream = futures::stream::try_unfold(
+        (archive, delivery),
+        |(mut archive, mut delivery)| async move {
+            Ok::<_, std::io::Error>(match archive.next().await? {
+                Some((chunk, read)) => {
+                    delivery.sent += read;
+                    Some((chunk, (archive, delivery)))
+                }
+                None => None,
+            })
+        },
+    );
+    slog::info!(
+        logger,
+        "GET";
+        "status" => 200,
+        "key" => &key,
+        "content-type" => format.content_type(),
+        "content-length" => archive_len,
+        "remaining-views" => record.remaining_views,
+    );
+    let mut res = base_res()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::CONTENT_LENGTH, archive_len)
+        .header(
+            header::CONTENT_DISPOSITION,
+            attachment(&format!("{}.{}", key, format.extension())),
+        );
+    if let Some(token) = token {
+        res = res.header("x-paste-download", token);
+    }
+    Ok(res.body(Body::wrap_stream(stream)).unwrap())
+}
+
+async fn revoke(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
+    token: String,
+) -> Result<Response<Body>, Error> {
+    match pastes.revoke(&Sha256::digest(token.as_bytes()))? {
+        Some((key, record)) => {
+            let rm = async {
+                if record.storage == Storage::Big {
+                    downloads.remove_blob(&key).await?;
+                }
+                Ok(())
+            };
+            futures::try_join!(pastes.flush(), rm)?;
+            receipts.ended(&key, &record, Outcome::Revoked, paste::now());
+            slog::info!(
+                logger,
+                "REVOKE";
+                "status" => 204,
+                "key" => key,
+            );
+            Ok(no_content())
+        }
+        None => {
+            slog::info!(
+                logger,
+                "REVOKE";
+                "status" => 404,
+            );
+            Err(Error::Status(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
+/// Generates a paste key from `len` bytes of CSPRNG output. Keys are independent of the paste
+/// content, so identical uploads never share (or resurrect) a link. Whether the key is free is
+/// only settled by [`PasteStore::create`].
+fn new_key(len: usize) -> String {
+    let mut bytes = vec![0; len];
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut bytes);
+    base64::encode_config(
+        &bytes,
+        base64::Config::new(base64::CharacterSet::UrlSafe, false),
+    )
+}
+
+/// Generates a token, such as a revocation token, returning it along with the hash that gets
+/// stored.
+fn new_token() -> (String, Vec<u8>) {
+    let mut token = [0; 32];
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut token);
+    let token = base64::encode_config(
+        token,
+        base64::Config::new(base64::CharacterSet::UrlSafe, false),
+    );
+    let token_hash = Sha256::digest(token.as_bytes()).to_vec();
+    (token, token_hash)
+}
+
+/// Server-side policy applied to every new paste.
+#[derive(Clone, Copy)]
+struct PastePolicy {
+    key_len: usize,
+    lifetimes: Lifetimes,
+    limits: Limits,
+    /// Wrong passphrases a paste allows before it burns.
+    passphrase_attempts: u32,
+    /// Whether small pastes are kept in the paste store itself. Off in secure-erase mode with
+    /// sled, whose log keeps burned values until the next startup compacts it; their bodies are
+    /// stored as blobs instead, which are overwritten as they burn.
+    inline: bool,
+}
+
+impl PastePolicy {
+    /// What is left of the policy for pastes created through a token with `scope`.
+    fn scoped(mut self, scope: &Scope) -> Self {
+        if let Some(max_size) = scope.max_size {
+            self.limits.max_paste_size = self.limits.max_paste_size.min(max_size);
+        }
+        if let Some(max_lifetime) = scope.max_lifetime {
+            self.lifetimes.max = self.lifetimes.max.min(max_lifetime).max(self.lifetimes.min);
+            self.lifetimes.default = self.lifetimes.default.min(self.lifetimes.max);
+        }
+        self
+    }
+}
+
+/// The headers describing a paste upload.
+struct NewPaste {
+    content_type: String,
+    expiration: Option<u64>,
+    ttl: Option<u64>,
+    max_views: Option<u64>,
+    label: Option<String>,
+    not_before: Option<u64>,
+    passphrase: Option<String>,
+    content_length: Option<u64>,
+}
+
+impl NewPaste {
+    /// Checks the headers against `policy`, returning the effective expiration and view limit. An
+    /// embargo must lift before the paste expires.
+    fn validate(&self, policy: &PastePolicy, now: u64) -> Result<(u64, u64), Error> {
+        let max_views = self.max_views.unwrap_or(1);
+        if max_views == 0 {
+            return Err(Error::StatusWithMessage(
+                StatusCode::BAD_REQUEST,
+                anyhow!("x-paste-max-views must be at least 1"),
+            ));
+        }
+        if self.label.as_ref().map_or(0, String::len) > MAX_LABEL_LENGTH {
+            return Err(Error::StatusWithMessage(
+                StatusCode::BAD_REQUEST,
+                anyhow!("x-paste-label may be at most {} bytes", MAX_LABEL_LENGTH),
+            ));
+        }
+        let expiration = policy
+            .lifetimes
+            .expiration(now, self.expiration, self.ttl)
+            .with_status(StatusCode::BAD_REQUEST)?;
+        if self
+            .not_before
+            .is_some_and(|not_before| not_before >= expiration)
+        {
+            return Err(Error::StatusWithMessage(
+                StatusCode::BAD_REQUEST,
+                anyhow!("x-paste-not-before must be before the expiration"),
+            ));
+        }
+        Ok((expiration, max_views))
+    }
+}
+
+/// Turns an upload that broke a storage limit into its 413 or 507.
+fn limit_error(e: std::io::Error) -> Error {
+    match LimitExceeded::from_io(&e) {
+        Some(limit) => Error::StatusWithMessage(limit.status(), anyhow!("{}", limit)),
+        None => e.into(),
+    }
+}
+
+fn new_paste_headers() -> impl Filter<Extract = (NewPaste,), Error = warp::Rejection> + Clone {
+    warp::header("content-type")
+        .and(warp::header::optional("x-paste-expiration"))
+        .and(warp::header::optional("x-paste-ttl"))
+        .and(warp::header::optional("x-paste-max-views"))
+        .and(warp::header::optional("x-paste-label"))
+        .and(warp::header::optional("x-paste-not-before"))
+        .and(warp::header::optional("x-paste-passphrase"))
+        .and(warp::header::optional("content-length"))
+        .map(
+            |content_type,
+             expiration,
+             ttl,
+             max_views,
+             label,
+             not_before,
+             passphrase,
+             content_length| NewPaste {
+                content_type,
+                expiration,
+                ttl,
+                max_views,
+                label,
+                not_before,
+                passphrase,
+                content_length,
+            },
+        )
+}
+
+/// Hashes the passphrase a paste is created with, if it has one.
+async fn hash_passphrase(
+    passphrase: Option<String>,
+    policy: PastePolicy,
+) -> Result<Option<Passphrase>, Error> {
+    let passphrase = match passphrase {
+        Some(passphrase) if passphrase.is_empty() => {
+            return Err(Error::StatusWithMessage(
+                StatusCode::BAD_REQUEST,
+                anyhow!("x-paste-passphrase must not be empty"),
+            ))
+        }
+        Some(passphrase) => passphrase,
+        None => return Ok(None),
+    };
+    let max_attempts = policy.passphrase_attempts;
+    let passphrase =
+        tokio::task::spawn_blocking(move || Passphrase::new(&passphrase, max_attempts)).await??;
+    Ok(Some(passphrase))
+}
+
+async fn new_data_small(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    policy: PastePolicy,
+    paste: NewPaste,
+    data: Bytes,
+    owner: String,
+) -> Result<NewDataRes, Error> {
+    if data.is_empty() {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("body required"),
+        ));
+    }
+    let now = paste::now();
+    let (expiration, max_views) = paste.validate(&policy, now)?;
+    let passphrase = hash_passphrase(paste.passphrase, policy).await?;
+    let size = data.len() as u64;
+    policy
+        .limits
+        .check_size(pastes.used(), size)
+        .and_then(|_| policy.limits.check_free_space(size))
+        .map_err(limit_error)?;
+    let staged = if policy.inline {
+        None
+    } else {
+        let (staged, mut writer) = pastes.blobs().stage().await?;
+        let written = async {
+            writer.write_all(&data).await?;
+            writer.shutdown().await
+        }
+        .await;
+        if let Err(e) = written {
+            pastes.blobs().discard(&staged).await?;
+            return Err(e.into());
+        }
+        Some(staged)
+    };
+    let mut record = PasteRecord::new(
+        if staged.is_some() {
+            Storage::Big
+        } else {
+            Storage::Inline
+        },
+        paste.content_type,
+        size,
+        (expiration, max_views),
+        Some(owner),
+        now,
+    );
+    record.label = paste.label;
+    record.not_before = paste.not_before;
+    record.passphrase = passphrase;
+    let body = match &staged {
+        Some(staged) => NewBody::Staged(staged),
+        None => NewBody::Inline(&data),
+    };
+    create_paste(&logger, &*pastes, &receipts, policy, record, body).await
+}
+
+async fn new_data<S: Stream<Item = Result<B, warp::Error>> + Unpin, B: Buf>(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    policy: PastePolicy,
+    paste: NewPaste,
+    data: S,
+    owner: String,
+) -> Result<NewDataRes, Error> {
+    let now = paste::now();
+    let (expiration, max_views) = paste.validate(&policy, now)?;
+    let passphrase = hash_passphrase(paste.passphrase, policy).await?;
+    if let Some(size) = paste.content_length {
+        // refuse a declared oversize body before reading any of it
+        policy
+            .limits
+            .check_size(pastes.used(), size)
+            .and_then(|_| policy.limits.check_free_space(size))
+            .map_err(limit_error)?;
+    }
+    let (staged, writer) = pastes.blobs().stage().await?;
+    let mut f = LimitWriter::new(writer, policy.limits, pastes.used());
+    let copied = async {
+        let size = tokio::io::copy(
+            &mut data
+                .map_ok(|mut buf| buf.copy_to_bytes(buf.remaining()).to_vec())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+                .into_async_read()
+                .compat_mut(),
+            &mut f,
+        )
+        .await?;
+        f.flush().await?;
+        f.shutdown().await?;
+        // other uploads may have finished while this one streamed
+        policy.limits.check_size(pastes.used(), size)?;
+        Ok(size)
+    }
+    .await;
+    let size = match copied {
+        Ok(size) => size,
+        Err(e) => {
+            pastes.blobs().discard(&staged).await?;
+            return Err(limit_error(e));
+        }
+    };
+    let mut record = PasteRecord::new(
+        Storage::Big,
+        paste.content_type,
+        size,
+        (expiration, max_views),
+        Some(owner),
+        now,
+    );
+    record.label = paste.label;
+    record.not_before = paste.not_before;
+    record.passphrase = passphrase;
+    create_paste(
+        &logger,
+        &*pastes,
+        &receipts,
+        policy,
+        record,
+        NewBody::Staged(&staged),
+    )
+    .await
+}
+
+/// Checks the name of the next file of a bundle. Names are plain file names, since they end up in
+/// archives that get extracted.
+fn check_file_name(name: Option<&str>, files: &[BundleEntry]) -> Result<String, Error> {
+    let name = name.ok_or_else(|| {
+        Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("every part must be a file with a filename"),
+        )
+    })?;
+    let problem = if name.is_empty() || name == "." || name == ".." {
+        "is not a file name"
+    } else if name.len() > MAX_FILE_NAME_LENGTH {
+        "is too long"
+    } else if name.contains(['/', '\\', '\0']) {
+        "may not contain slashes or NUL"
+    } else if files.iter().any(|file| file.name == name) {
+        "appears twice"
+    } else {
+        return Ok(name.to_owned());
+    };
+    Err(Error::StatusWithMessage(
+        StatusCode::BAD_REQUEST,
+        anyhow!("file name {:?} {}", name, problem),
+    ))
+}
+
+/// Stores the files of a multipart upload back to back in one blob, as a bundle behind a single
+/// key. The blob is staged and streamed through the size limits just like the body of a big paste
+/// in [`new_data`]; nothing is hashed along the way, as keys are random rather than digests.
+async fn new_bundle(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    policy: PastePolicy,
+    paste: NewPaste,
+    mut form: warp::multipart::FormData,
+    owner: String,
+) -> Result<NewDataRes, Error> {
+    let now = paste::now();
+    let (expiration, max_views) = paste.validate(&policy, now)?;
+    let passphrase = hash_passphrase(paste.passphrase, policy).await?;
+    if let Some(size) = paste.content_length {
+        // the multipart framing counts too, but only by a little
+        policy
+            .limits
+            .check_size(pastes.used(), size)
+            .and_then(|_| policy.limits.check_free_space(size))
+            .map_err(limit_error)?;
+    }
+    let (staged, writer) = pastes.blobs().stage().await?;
+    let mut f = LimitWriter::new(writer, policy.limits, pastes.used());
+    let mut files = Vec::new();
+    let copied = async {
+        let mut offset = 0;
+        while let Some(part) = form
+            .try_next()
+            .await
+            .with_status(StatusCode::BAD_REQUEST)
+            .with_message(|| anyhow!("parsing multipart body"))?
+        {
+            if files.len() == MAX_BUNDLE_FILES {
+                return Err(Error::StatusWithMessage(
+                    StatusCode::BAD_REQUEST,
+                    anyhow!("a bundle may hold at most {} files", MAX_BUNDLE_FILES),
+                ));
+            }
+            let name = check_file_name(part.filename(), &files)?;
+            let content_type = part
+                .content_type()
+                .unwrap_or("application/octet-stream")
+                .to_owned();
+            let size = tokio::io::copy(
+                &mut part
+                    .stream()
+                    .map_ok(|mut buf| buf.copy_to_bytes(buf.remaining()).to_vec())
+                    .map_err(std::io::Error::other)
+                    .into_async_read()
+                    .compat_mut(),
+                &mut f,
+            )
+            .await
+            .map_err(limit_error)?;
+            files.push(BundleEntry {
+                name,
+                content_type,
+                offset,
+                size,
+            });
+            offset += size;
+        }
+        if files.is_empty() {
+            return Err(Error::StatusWithMessage(
+                StatusCode::BAD_REQUEST,
+                anyhow!("at least one file required"),
+            ));
+        }
+        f.flush().await.map_err(limit_error)?;
+        f.shutdown().await.map_err(limit_error)?;
+        // other uploads may have finished while this one streamed
+        policy
+            .limits
+            .check_size(pastes.used(), offset)
+            .map_err(limit_error)?;
+        Ok(offset)
+    }
+    .await;
+    let size = match copied {
+        Ok(size) => size,
+        Err(e) => {
+            pastes.blobs().discard(&staged).await?;
+            return Err(e);
+        }
+    };
+    let mut record = PasteRecord::new(
+        Storage::Big,
+        BUNDLE_CONTENT_TYPE.to_owned(),
+        size,
+        (expiration, max_views),
+        Some(owner),
+        now,
+    );
+    record.label = paste.label;
+    record.not_before = paste.not_before;
+    record.passphrase = passphrase;
+    record.bundle = Some(files);
+    create_paste(
+        &logger,
+        &*pastes,
+        &receipts,
+        policy,
+        record,
+        NewBody::Staged(&staged),
+    )
+    .await
+}
+
+/// Where the body of a new paste comes from.
+enum NewBody<'a> {
+    Inline(&'a [u8]),
+    /// A blob staged with [`BlobStore::stage`](store::BlobStore::stage), discarded should the
+    /// paste not be created.
+    Staged(&'a str),
+    /// A finished file, left in place should the paste not be created.
+    File(&'a Path),
+}
+
+/// Stores a new paste under a fresh key, handing out the key, its revocation token and id. The
+/// key is claimed by creating the record, drawing another on a collision, and only then is the
+/// blob of a big paste published under it; should that fail, the record is taken back.
+async fn create_paste(
+    logger: &slog::Logger,
+    pastes: &dyn PasteStore,
+    receipts: &Receipts,
+    policy: PastePolicy,
+    mut record: PasteRecord,
+    body: NewBody<'_>,
+) -> Result<NewDataRes, Error> {
+    let (revocation_token, revocation_hash) = new_token();
+    record.revocation_hash = Some(revocation_hash);
+    let id = webhook::new_id();
+    record.id = Some(id.clone());
+    let inline = match body {
+        NewBody::Inline(data) => Some(data),
+        _ => None,
+    };
+    let key = loop {
+        let key = new_key(policy.key_len);
+        match pastes.create(&key, &record, inline).await {
+            Ok(true) => break key,
+            Ok(false) => continue,
+            Err(e) => {
+                if let NewBody::Staged(staged) = body {
+                    pastes.blobs().discard(staged).await?;
+                }
+                return Err(e);
+            }
+        }
+    };
+    let published = match body {
+        NewBody::Inline(_) => Ok(0),
+        NewBody::Staged(staged) => pastes.blobs().commit(staged, &key).await,
+        NewBody::File(path) => pastes.blobs().import(path, &key).await,
+    };
+    if let Err(e) = published {
+        pastes.take(&key)?;
+        if let NewBody::Staged(staged) = body {
+            pastes.blobs().discard(staged).await?;
+        }
+        return Err(e);
+    }
+    receipts.created(&record, record.created_at);
+    slog::info!(
+        logger,
+        "CREATE";
+        "status" => 200,
+        "key" => &key,
+        "content-type" => &record.content_type,
+        "content-length" => record.size,
+        "expiration" => %time::OffsetDateTime::from_unix_timestamp(record.expiration as i64)?,
+        "max-views" => record.options.max_views,
+        "owner" => record.owner,
+    );
+    Ok(NewDataRes {
+        hash: key,
+        revocation_token,
+        expiration: record.expiration,
+        id,
+    })
+}
+
+/// The options of a new invite.
+#[derive(serde::Deserialize)]
+struct NewInvite {
+    /// Seconds until the link stops working.
+    ttl: Option<u64>,
+    /// Largest paste accepted, in bytes. Defaults to the largest allowed at all.
+    max_size: Option<u64>,
+    note: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct NewInviteRes {
+    id: String,
+    /// Goes in the link handed to the uploader. Only its hash is kept.
+    token: String,
+    expires: u64,
+    max_size: u64,
+}
+
+async fn new_invite(
+    logger: Arc<slog::Logger>,
+    invites: Invites,
+    policy: PastePolicy,
+    body: Bytes,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    let new: NewInvite = serde_json::from_slice(&body).with_status(StatusCode::BAD_REQUEST)?;
+    let max_size = new.max_size.unwrap_or(policy.limits.max_paste_size);
+    if max_size == 0 || max_size > policy.limits.max_paste_size {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!(
+                "max_size must be between 1 and {}",
+                policy.limits.max_paste_size
+            ),
+        ));
+    }
+    let ttl = new.ttl.unwrap_or(INVITE_LIFETIME.as_secs());
+    if ttl == 0 || ttl > policy.lifetimes.max {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("ttl must be between 1 and {}", policy.lifetimes.max),
+        ));
+    }
+    if new.note.as_ref().map_or(0, String::len) > MAX_LABEL_LENGTH {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("note may be at most {} bytes", MAX_LABEL_LENGTH),
+        ));
+    }
+    let now = paste::now();
+    let invite = Invite {
+        id: webhook::new_id(),
+        created_by: user,
+        created_at: now,
+        expires: now + ttl,
+        max_size,
+        note: new.note,
+    };
+    let (token, token_hash) = new_token();
+    invites.insert(&token_hash, &invite)?;
+    invites.flush().await?;
+    slog::info!(
+        logger,
+        "INVITE";
+        "status" => 200,
+        "invite" => &invite.id,
+        "created-by" => &invite.created_by,
+        "expires" => invite.expires,
+        "max-size" => max_size,
+    );
+    Ok(ok_json(&NewInviteRes {
+        id: invite.id,
+        token,
+        expires: invite.expires,
+        max_size,
+    }))
+}
+
+/// The unused invites `user` has made, soonest to expire first.
+async fn list_invites(
+    logger: Arc<slog::Logger>,
+    invites: Invites,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    let now = paste::now();
+    let invites: Vec<Invite> = invites
+        .made_by(&user)?
+        .into_iter()
+        .map(|(_, invite)| invite)
+        .filter(|invite| invite.expires > now)
+        .collect();
+    slog::info!(
+        logger,
+        "INVITES";
+        "status" => 200,
+        "count" => invites.len(),
+    );
+    Ok(ok_json(&invites))
+}
+
+async fn cancel_invite(
+    logger: Arc<slog::Logger>,
+    invites: Invites,
+    user: String,
+    id: String,
+) -> Result<Response<Body>, Error> {
+    let token_hash = invites
+        .made_by(&user)?
+        .into_iter()
+        .find(|(_, invite)| invite.id == id)
+        .map(|(token_hash, _)| token_hash);
+    match token_hash {
+        Some(token_hash) => {
+            invites.remove(&token_hash)?;
+            invites.flush().await?;
+            slog::info!(
+                logger,
+                "CANCEL INVITE";
+                "status" => 204,
+                "invite" => id,
+            );
+            Ok(no_content())
+        }
+        None => {
+            slog::info!(
+                logger,
+                "CANCEL INVITE";
+                "status" => 404,
+                "invite" => id,
+            );
+            Err(Error::Status(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
+/// Stores the one paste an invite allows, on behalf of the user who made it. The uploader learns
+/// nothing about the paste, so its key only ever reaches the inviter. Nor does the uploader get a
+/// say in how the paste may be read: its lifetime and views are the defaults, and any passphrase or
+/// embargo asked for is ignored. Invites of a disabled user stop working until they are enabled
+/// again.
+#[allow(clippy::too_many_arguments)]
+async fn invited_data<S: Stream<Item = Result<B, warp::Error>> + Unpin, B: Buf>(
+    logger: Arc<slog::Logger>,
+    invites: Invites,
+    users: Users,
+    pastes: Store,
+    receipts: Receipts,
+    policy: PastePolicy,
+    token: String,
+    mut paste: NewPaste,
+    data: S,
+) -> Result<Response<Body>, Error> {
+    let token_hash = Sha256::digest(token.as_bytes()).to_vec();
+    let invite = match invites.claim(&token_hash, paste::now())? {
+        Some(invite) => invite,
+        None => {
+            slog::info!(
+                logger,
+                "INVITED";
+                "status" => 404,
+            );
+            return Err(Error::Status(StatusCode::NOT_FOUND));
+        }
+    };
+    match users.get(&invite.created_by)? {
+        Some(user) if !user.disabled => (),
+        inviter => {
+            // a deleted user's invites go with them, a disabled user's wait
+            if inviter.is_some() {
+                invites.insert(&token_hash, &invite)?;
+            }
+            slog::info!(
+                logger,
+                "INVITED";
+                "status" => 404,
+                "invite" => &invite.id,
+                "created-by" => &invite.created_by,
+            );
+            return Err(Error::Status(StatusCode::NOT_FOUND));
+        }
+    }
+    let mut limited = policy;
+    limited.limits.max_paste_size = invite.max_size.min(policy.limits.max_paste_size);
+    paste.expiration = None;
+    paste.ttl = None;
+    paste.max_views = None;
+    paste.not_before = None;
+    paste.passphrase = None;
+    if invite.note.is_some() {
+        paste.label = invite.note.clone();
+    }
+    let res = new_data(
+        logger.clone(),
+        pastes,
+        receipts,
+        limited,
+        paste,
+        data,
+        invite.created_by.clone(),
+    )
+    .await;
+    if let Err(e) = res {
+        // a failed upload leaves the invite for another try
+        if invite.expires > paste::now() {
+            invites.insert(&token_hash, &invite)?;
+        }
+        return Err(e);
+    }
+    invites.flush().await?;
+    slog::info!(
+        logger,
+        "INVITED";
+        "status" => 204,
+        "invite" => &invite.id,
+        "created-by" => &invite.created_by,
+    );
+    Ok(no_content())
+}
+
+async fn clean_invites(logger: Arc<slog::Logger>, invites: Invites) -> Option<u64> {
+    let res = tokio::task::spawn_blocking(move || {
+        let expired = invites.expired(paste::now())?;
+        for token_hash in &expired {
+            invites.remove(token_hash)?;
+        }
+        Ok((expired.len(), invites.next_expiration()?))
+    })
+    .await
+    .map_err(Error::from)
+    .and_then(|res| res);
+    match res {
+        Ok((deleted, next)) => {
+            if deleted > 0 {
+                slog::info!(logger, "invite cleaner complete"; "deleted" => deleted);
+            }
+            next
+        }
+        Err(e) => {
+            slog::error!(
+                logger,
+                "ERROR";
+                "context" => "invite cleaner",
+                "reason" => %e,
+            );
+            None
+        }
+    }
+}
+
+/// The inbox, or a 404 if none is configured.
+fn enabled(inbox: Option<Inbox>) -> Result<Inbox, Error> {
+    inbox.ok_or_else(|| {
+        Error::StatusWithMessage(StatusCode::NOT_FOUND, anyhow!("there is no inbox"))
+    })
+}
+
+/// The age public key messages are encrypted to, for sources who would rather encrypt them
+/// before they leave their hands.
+async fn inbox_key(
+    logger: Arc<slog::Logger>,
+    inbox: Option<Inbox>,
+) -> Result<Response<Body>, Error> {
+    let inbox = enabled(inbox)?;
+    slog::info!(
+        logger,
+        "INBOX KEY";
+        "status" => 200,
+    );
+    Ok(ok()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(inbox.recipient.to_string().into())
+        .unwrap())
+}
+
+#[derive(serde::Serialize)]
+struct InboxChallenge {
+    challenge: String,
+    /// Leading zero bits asked of the SHA-256 of a stamp.
+    difficulty: u32,
+    expires: u64,
+}
+
+/// A challenge to register a source with, by proof of work.
+async fn inbox_challenge(
+    logger: Arc<slog::Logger>,
+    inbox: Option<Inbox>,
+) -> Result<Response<Body>, Error> {
+    let inbox = enabled(inbox)?;
+    let now = paste::now();
+    slog::info!(
+        logger,
+        "INBOX CHALLENGE";
+        "status" => 200,
+    );
+    Ok(ok_json(&InboxChallenge {
+        challenge: inbox.challenge(now),
+        difficulty: inbox.difficulty,
+        expires: now + inbox::CHALLENGE_LIFETIME,
+    }))
+}
+
+#[derive(serde::Serialize)]
+struct InboxSource {
+    /// Goes in the `x-inbox-source` header of each message. Only its hash is kept.
+    source: String,
+    expires: u64,
+    /// Messages the source may leave per hour.
+    rate_limit: u32,
+}
+
+/// Registers a source of inbox messages, for the stamp in `x-inbox-stamp`.
+async fn new_source(
+    logger: Arc<slog::Logger>,
+    inbox: Option<Inbox>,
+    stamp: String,
+) -> Result<Response<Body>, Error> {
+    let inbox = enabled(inbox)?;
+    let now = paste::now();
+    let source = inbox.register(&stamp, now).map_err(|e| {
+        let status = match e {
+            StampError::Malformed => StatusCode::BAD_REQUEST,
+            StampError::BadChallenge | StampError::TooLittleWork => StatusCode::FORBIDDEN,
+        };
+        Error::StatusWithMessage(status, anyhow!("{}", e))
+    })?;
+    slog::info!(
+        logger,
+        "INBOX SOURCE";
+        "status" => 200,
+    );
+    Ok(ok_json(&InboxSource {
+        source,
+        expires: now + inbox::SOURCE_LIFETIME,
+        rate_limit: inbox.rate_limit,
+    }))
+}
+
+/// Leaves a message in the inbox from the source named by `x-inbox-source`. Unless the source
+/// sent an age file of its own, the message is encrypted to the inbox key on its way to disk. The
+/// source learns nothing back, so the message can only be found through the inbox. Only messages
+/// that are stored count against the source's limit.
+#[allow(clippy::too_many_arguments)]
+async fn new_message<S: Stream<Item = Result<B, warp::Error>> + Unpin, B: Buf>(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    policy: PastePolicy,
+    inbox: Option<Inbox>,
+    source: Option<String>,
+    content_type: Option<String>,
+    content_length: Option<u64>,
+    data: S,
+) -> Result<Response<Body>, Error> {
+    let inbox = enabled(inbox)?;
+    let now = paste::now();
+    let content_type = content_type.unwrap_or_else(|| "application/octet-stream".to_owned());
+    if content_type.len() > MAX_LABEL_LENGTH {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("Content-Type may be at most {} bytes", MAX_LABEL_LENGTH),
+        ));
+    }
+    let mut limits = policy.limits;
+    limits.max_paste_size = inbox.max_size;
+    if let Some(size) = content_length {
+        // encryption only adds to the size, so this is already too much
+        limits
+            .check_size(pastes.used(), size)
+            .and_then(|_| limits.check_free_space(size))
+            .map_err(limit_error)?;
+    }
+    let source = source.unwrap_or_default();
+    match inbox.admit(&source, now) {
+        Ok(()) => (),
+        Err(Refusal::UnknownSource) => {
+            return Err(Error::StatusWithMessage(
+                StatusCode::UNAUTHORIZED,
+                anyhow!("x-inbox-source must hold a token from /api/inbox/sources"),
+            ));
+        }
+        Err(Refusal::RateLimited(retry_after)) => {
+            slog::info!(
+                logger,
+                "INBOX";
+                "status" => 429,
+                "retry-after" => retry_after,
+            );
+            return Ok(too_many_requests(retry_after));
+        }
+    }
+    let mut reader = data
+        .map_ok(|mut buf| buf.copy_to_bytes(buf.remaining()).to_vec())
+        .map_err(std::io::Error::other)
+        .into_async_read();
+    let (staged, writer) = pastes.blobs().stage().await?;
+    let mut f = LimitWriter::new(writer, limits, pastes.used());
+    let mut encrypted_by_source = false;
+    let copied = async {
+        let mut prefix = vec![0; inbox::MAGIC_LEN];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match futures::AsyncReadExt::read(&mut reader, &mut prefix[filled..]).await? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        prefix.truncate(filled);
+        encrypted_by_source = inbox::is_age(&prefix);
+        let reader = futures::AsyncReadExt::chain(prefix.as_slice(), reader);
+        if encrypted_by_source {
+            futures::io::copy(reader, &mut f.compat_mut()).await?;
+        } else {
+            let mut output = inbox.encrypt(f.compat_mut()).await?;
+            futures::io::copy(reader, &mut output).await?;
+            futures::AsyncWriteExt::close(&mut output).await?;
+        }
+        f.flush().await?;
+        f.shutdown().await?;
+        // other uploads may have finished while this one streamed
+        limits.check_size(pastes.used(), f.written())?;
+        Ok(f.written())
+    }
+    .await;
+    let size = match copied {
+        Ok(size) => size,
+        Err(e) => {
+            inbox.refund(&source, now);
+            pastes.blobs().discard(&staged).await?;
+            return Err(limit_error(e));
+        }
+    };
+    let mut record = PasteRecord::new(
+        Storage::Big,
+        "application/octet-stream".to_owned(),
+        size,
+        (now + policy.lifetimes.max, 1),
+        None,
+        now,
+    );
+    record.inbox = Some(InboxMessage {
+        content_type,
+        encrypted_by_source,
+    });
+    if let Err(e) = create_paste(
+        &logger,
+        &*pastes,
+        &receipts,
+        policy,
+        record,
+        NewBody::Staged(&staged),
+    )
+    .await
+    {
+        inbox.refund(&source, now);
+        return Err(e);
+    }
+    slog::info!(
+        logger,
+        "INBOX";
+        "status" => 204,
+        "encrypted-by-source" => encrypted_by_source,
+    );
+    Ok(no_content())
+}
+
+/// A message as listed in the inbox.
+#[derive(serde::Serialize)]
+struct InboxSummary {
+    key: String,
+    /// Of the ciphertext.
+    size: u64,
     content_type: String,
+    encrypted_by_source: bool,
+    created_at: u64,
     expiration: u64,
+}
+
+/// Every unread message in the inbox, newest first.
+async fn list_inbox(logger: Arc<slog::Logger>, pastes: Store) -> Result<Response<Body>, Error> {
+    let now = paste::now();
+    let mut messages: Vec<InboxSummary> = pastes
+        .records()?
+        .into_iter()
+        .filter(|(_, record)| !record.is_expired(now))
+        .filter_map(|(key, record)| {
+            let message = record.inbox?;
+            Some(InboxSummary {
+                key,
+                size: record.size,
+                content_type: message.content_type,
+                encrypted_by_source: message.encrypted_by_source,
+                created_at: record.created_at,
+                expiration: record.expiration,
+            })
+        })
+        .collect();
+    messages.sort_by(|a, b| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then_with(|| a.key.cmp(&b.key))
+    });
+    slog::info!(
+        logger,
+        "INBOX LIST";
+        "status" => 200,
+        "count" => messages.len(),
+    );
+    Ok(ok_json(&messages))
+}
+
+/// Whether `key` names a live inbox message.
+fn is_message(pastes: &dyn PasteStore, key: &str) -> Result<bool, Error> {
+    Ok(pastes
+        .get(key)?
+        .is_some_and(|record| record.inbox.is_some() && !record.is_expired(paste::now())))
+}
+
+/// Downloads the ciphertext of an inbox message, which burns once all of it has been sent.
+async fn read_message(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    downloads: Downloads,
+    key: String,
+    read: ReadRequest,
+) -> Result<Response<Body>, Error> {
+    if !is_message(&*pastes, &key)? {
+        slog::info!(
+            logger,
+            "INBOX READ";
+            "status" => 404,
+            "key" => key,
+        );
+        return Err(Error::Status(StatusCode::NOT_FOUND));
+    }
+    download(logger, pastes, downloads, key, None, read, paste::now()).await
+}
+
+/// Burns an inbox message without reading it.
+async fn burn_message(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
+    key: String,
+) -> Result<Response<Body>, Error> {
+    if !is_message(&*pastes, &key)? {
+        slog::info!(
+            logger,
+            "INBOX BURN";
+            "status" => 404,
+            "key" => key,
+        );
+        return Err(Error::Status(StatusCode::NOT_FOUND));
+    }
+    data(
+        logger,
+        pastes,
+        receipts,
+        downloads,
+        key,
+        Method::DELETE,
+        ReadRequest::default(),
+    )
+    .await
+}
+
+const TUS_VERSION: &str = "1.0.0";
+
+fn tus_res() -> ResponseBuilder {
+    base_res().header("tus-resumable", TUS_VERSION)
+}
+
+/// The 412 owed to a tus request that doesn't speak our protocol version, if it doesn't.
+fn tus_unsupported(version: &Option<String>) -> Option<Response<Body>> {
+    (version.as_deref() != Some(TUS_VERSION)).then(|| {
+        base_res()
+            .status(StatusCode::PRECONDITION_FAILED)
+            .header("tus-version", TUS_VERSION)
+            .body(Bytes::new().into())
+            .unwrap()
+    })
+}
+
+fn tus_options(policy: PastePolicy) -> Response<Body> {
+    tus_res()
+        .status(StatusCode::NO_CONTENT)
+        .header("tus-version", TUS_VERSION)
+        .header("tus-extension", "creation,expiration,termination")
+        .header("tus-max-size", policy.limits.max_paste_size)
+        .body(Bytes::new().into())
+        .unwrap()
+}
+
+fn upload_expires(upload: &Upload) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(upload.expires))
+}
+
+/// Parses a numeric tus header, which must be present.
+fn tus_number(name: &str, value: Option<String>) -> Result<u64, Error> {
+    value
+        .ok_or_else(|| anyhow!("{} required", name))
+        .and_then(|value| Ok(value.parse::<u64>()?))
+        .with_status(StatusCode::BAD_REQUEST)
+}
+
+/// The content type of an upload, from the `content-type` entry of its `Upload-Metadata` or else
+/// the `filetype` entry that tus-js-client sends.
+fn upload_content_type(metadata: &Option<String>) -> Result<String, Error> {
+    let entries: Vec<(&str, &str)> = metadata
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| {
+            let mut entry = entry.trim().splitn(2, ' ');
+            Some((entry.next()?, entry.next().unwrap_or_default()))
+        })
+        .collect();
+    for name in ["content-type", "filetype"] {
+        if let Some((_, value)) = entries.iter().find(|(key, _)| *key == name) {
+            let value = base64::decode(value)
+                .map_err(AnyError::from)
+                .and_then(|value| Ok(String::from_utf8(value)?))
+                .with_status(StatusCode::BAD_REQUEST)
+                .with_message(|| anyhow!("parsing Upload-Metadata"))?;
+            if !value.is_empty() {
+                return Ok(value);
+            }
+        }
+    }
+    Ok("application/octet-stream".to_owned())
+}
+
+fn upload_paste(upload: &Upload) -> NewPaste {
+    NewPaste {
+        content_type: upload.content_type.clone(),
+        expiration: upload.expiration,
+        ttl: upload.ttl,
+        max_views: upload.max_views,
+        label: upload.label.clone(),
+        not_before: upload.not_before,
+        // hashed when the upload was created
+        passphrase: None,
+        content_length: Some(upload.length),
+    }
+}
+
+/// The headers of a tus creation request.
+struct NewUpload {
+    tus_resumable: Option<String>,
+    length: Option<String>,
+    metadata: Option<String>,
+    expiration: Option<u64>,
+    ttl: Option<u64>,
+    max_views: Option<u64>,
+    label: Option<String>,
+    not_before: Option<u64>,
+    passphrase: Option<String>,
+}
+
+fn new_upload_headers() -> impl Filter<Extract = (NewUpload,), Error = warp::Rejection> + Clone {
+    warp::header::optional("tus-resumable")
+        .and(warp::header::optional("upload-length"))
+        .and(warp::header::optional("upload-metadata"))
+        .and(warp::header::optional("x-paste-expiration"))
+        .and(warp::header::optional("x-paste-ttl"))
+        .and(warp::header::optional("x-paste-max-views"))
+        .and(warp::header::optional("x-paste-label"))
+        .and(warp::header::optional("x-paste-not-before"))
+        .and(warp::header::optional("x-paste-passphrase"))
+        .map(
+            |tus_resumable,
+             length,
+             metadata,
+             expiration,
+             ttl,
+             max_views,
+             label,
+             not_before,
+             passphrase| NewUpload {
+                tus_resumable,
+                length,
+                metadata,
+                expiration,
+                ttl,
+                max_views,
+                label,
+                not_before,
+                passphrase,
+            },
+        )
+}
+
+/// Starts a resumable upload. The paste options are checked now, so a doomed upload is refused
+/// before any of it is sent, and again when the upload completes.
+async fn new_upload(
+    logger: Arc<slog::Logger>,
+    uploads: Uploads,
+    pastes: Store,
+    policy: PastePolicy,
+    new: NewUpload,
+    owner: String,
+) -> Result<Response<Body>, Error> {
+    if let Some(res) = tus_unsupported(&new.tus_resumable) {
+        return Ok(res);
+    }
+    let length = tus_number("Upload-Length", new.length)?;
+    if length == 0 {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("body required"),
+        ));
+    }
+    let now = paste::now();
+    let mut upload = Upload {
+        length,
+        content_type: upload_content_type(&new.metadata)?,
+        expiration: new.expiration,
+        ttl: new.ttl,
+        max_views: new.max_views,
+        label: new.label,
+        not_before: new.not_before,
+        passphrase: None,
+        metadata: new.metadata,
+        expires: now + UPLOAD_LIFETIME.as_secs(),
+        owner: Some(owner),
+    };
+    upload_paste(&upload).validate(&policy, now)?;
+    upload.passphrase = hash_passphrase(new.passphrase, policy).await?;
+    policy
+        .limits
+        .check_size(pastes.used(), length)
+        .and_then(|_| policy.limits.check_free_space(length))
+        .map_err(limit_error)?;
+    let id = uploads.create(&upload).await?;
+    slog::info!(
+        logger,
+        "UPLOAD";
+        "status" => 201,
+        "upload" => &id,
+        "upload-length" => length,
+    );
+    Ok(tus_res()
+        .status(StatusCode::CREATED)
+        .header(header::LOCATION, format!("/api/uploads/{}", id))
+        .header("upload-expires", upload_expires(&upload))
+        .body(Bytes::new().into())
+        .unwrap())
+}
+
+/// The upload `id`, if `user` may touch it. An upload belongs to whoever started it, though admins
+/// may touch any; to anyone else it doesn't exist.
+fn owned_upload(uploads: &Uploads, users: &Users, id: &str, user: &str) -> Result<Upload, Error> {
+    match uploads.get(id)? {
+        Some(upload) if upload.owner.as_deref() == Some(user) || users.is_admin(user)? => {
+            Ok(upload)
+        }
+        _ => Err(Error::Status(StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Reports how much of an upload has arrived.
+async fn upload_offset(
+    uploads: Uploads,
+    users: Users,
+    id: String,
+    tus_resumable: Option<String>,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    if let Some(res) = tus_unsupported(&tus_resumable) {
+        return Ok(res);
+    }
+    let upload = owned_upload(&uploads, &users, &id, &user)?;
+    let mut res = tus_res()
+        .status(StatusCode::OK)
+        .header("upload-offset", uploads.offset(&id).await?)
+        .header("upload-length", upload.length)
+        .header("upload-expires", upload_expires(&upload));
+    if let Some(metadata) = &upload.metadata {
+        res = res.header("upload-metadata", metadata);
+    }
+    Ok(res.body(Bytes::new().into()).unwrap())
+}
+
+/// The headers of a tus PATCH request.
+struct UploadChunk {
+    tus_resumable: Option<String>,
+    content_type: Option<String>,
+    offset: Option<String>,
+    content_length: Option<u64>,
+}
+
+fn upload_chunk_headers() -> impl Filter<Extract = (UploadChunk,), Error = warp::Rejection> + Clone
+{
+    warp::header::optional("tus-resumable")
+        .and(warp::header::optional("content-type"))
+        .and(warp::header::optional("upload-offset"))
+        .and(warp::header::optional("content-length"))
+        .map(
+            |tus_resumable, content_type, offset, content_length| UploadChunk {
+                tus_resumable,
+                content_type,
+                offset,
+                content_length,
+            },
+        )
+}
+
+/// Appends a chunk to an upload at the offset the client expects. Whatever arrives is kept even
+/// if the connection drops, so the client can resume from there; the upload becomes a paste as
+/// soon as its last byte arrives.
+#[allow(clippy::too_many_arguments)]
+async fn append_upload<S: Stream<Item = Result<B, warp::Error>> + Unpin, B: Buf>(
+    logger: Arc<slog::Logger>,
+    uploads: Uploads,
+    users: Users,
+    pastes: Store,
+    receipts: Receipts,
+    policy: PastePolicy,
+    id: String,
+    chunk: UploadChunk,
     data: S,
-) -> Result<String, Error> {
-    let tmp = Path::new("tmp");
-    tokio::fs::create_dir_all(tmp).await?;
-    let mut tmp_file;
-    while {
-        tmp_file = format!("{}.tmp", rand::RngCore::next_u32(&mut rand::thread_rng()));
-        tokio::fs::metadata(tmp.join(&tmp_file)).await.is_ok()
-    } {}
-    let mut f = HashWriter::<Sha256, _>::new(tokio::fs::File::create(tmp.join(&tmp_file)).await?);
-    tokio::io::copy(
+    user: String,
+) -> Result<Response<Body>, Error> {
+    if let Some(res) = tus_unsupported(&chunk.tus_resumable) {
+        return Ok(res);
+    }
+    if chunk.content_type.as_deref() != Some("application/offset+octet-stream") {
+        return Err(Error::Status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+    }
+    let upload = owned_upload(&uploads, &users, &id, &user)?;
+    let _lock = uploads.lock(&id).ok_or_else(|| {
+        Error::StatusWithMessage(
+            StatusCode::CONFLICT,
+            anyhow!("upload is already being written"),
+        )
+    })?;
+    let offset = uploads.offset(&id).await?;
+    if tus_number("Upload-Offset", chunk.offset)? != offset {
+        return Err(Error::StatusWithMessage(
+            StatusCode::CONFLICT,
+            anyhow!("upload is at offset {}", offset),
+        ));
+    }
+    let remaining = upload.length - offset;
+    if chunk.content_length.unwrap_or(0) > remaining {
+        return Err(Error::StatusWithMessage(
+            StatusCode::BAD_REQUEST,
+            anyhow!("chunk overruns Upload-Length"),
+        ));
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(uploads.path(&id))
+        .await?;
+    let limits = Limits {
+        max_paste_size: remaining,
+        ..policy.limits
+    };
+    let mut writer = LimitWriter::new(&mut file, limits, pastes.used() + offset);
+    let copied = tokio::io::copy(
         &mut data
             .map_ok(|mut buf| buf.copy_to_bytes(buf.remaining()).to_vec())
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            .map_err(std::io::Error::other)
             .into_async_read()
             .compat_mut(),
-        &mut f,
+        &mut writer,
+    )
+    .await;
+    writer.flush().await?;
+    uploads.sync(&file).await?;
+    copied.map_err(limit_error)?;
+    let offset = uploads.offset(&id).await?;
+    let res = tus_res()
+        .status(StatusCode::NO_CONTENT)
+        .header("upload-offset", offset)
+        .header("upload-expires", upload_expires(&upload));
+    if offset < upload.length {
+        slog::info!(
+            logger,
+            "PATCH";
+            "status" => 204,
+            "upload" => &id,
+            "upload-offset" => offset,
+        );
+        return Ok(res.body(Bytes::new().into()).unwrap());
+    }
+    let created =
+        finish_upload(&logger, &uploads, &*pastes, &receipts, policy, &id, &upload).await?;
+    Ok(res
+        .header("x-paste-hash", created.hash)
+        .header("x-paste-revocation-token", created.revocation_token)
+        .header("x-paste-expiration", created.expiration)
+        .header("x-paste-id", created.id)
+        .body(Bytes::new().into())
+        .unwrap())
+}
+
+/// Turns a fully received upload into a paste. An upload whose options have become invalid,
+/// such as an `x-paste-expiration` that has passed, is discarded; one refused for lack of space is
+/// kept, so an empty PATCH can complete it later.
+async fn finish_upload(
+    logger: &slog::Logger,
+    uploads: &Uploads,
+    pastes: &dyn PasteStore,
+    receipts: &Receipts,
+    policy: PastePolicy,
+    id: &str,
+    upload: &Upload,
+) -> Result<NewDataRes, Error> {
+    let now = paste::now();
+    let options = match upload_paste(upload).validate(&policy, now) {
+        Ok(options) => options,
+        Err(e) => {
+            uploads.remove(id).await?;
+            return Err(e);
+        }
+    };
+    policy
+        .limits
+        .check_size(pastes.used(), upload.length)
+        .map_err(limit_error)?;
+    let mut record = PasteRecord::new(
+        Storage::Big,
+        upload.content_type.clone(),
+        upload.length,
+        options,
+        upload.owner.clone(),
+        now,
+    );
+    record.label = upload.label.clone();
+    record.not_before = upload.not_before;
+    record.passphrase = upload.passphrase.clone();
+    let created = create_paste(
+        logger,
+        pastes,
+        receipts,
+        policy,
+        record,
+        NewBody::File(&uploads.path(id)),
     )
     .await?;
-    let key = base64::encode_config(
-        &f.finish().await?,
-        base64::Config::new(base64::CharacterSet::UrlSafe, true),
-    );
-    let big = Path::new("big");
-    tokio::fs::create_dir_all(big).await?;
-    tokio::fs::rename(tmp.join(&tmp_file), big.join(&key)).await?;
-    let len = tokio::fs::metadata(big.join(&key)).await?.len();
-    data_tree.insert(&key, b"")?;
-    content_type_tree.insert(&key, content_type.as_bytes())?;
-    expiration_tree.insert(&key, &u64::to_be_bytes(expiration))?;
+    uploads.forget(id)?;
+    Ok(created)
+}
+
+async fn terminate_upload(
+    logger: Arc<slog::Logger>,
+    uploads: Uploads,
+    users: Users,
+    id: String,
+    tus_resumable: Option<String>,
+    user: String,
+) -> Result<Response<Body>, Error> {
+    if let Some(res) = tus_unsupported(&tus_resumable) {
+        return Ok(res);
+    }
+    owned_upload(&uploads, &users, &id, &user)?;
+    let _lock = uploads.lock(&id).ok_or_else(|| {
+        Error::StatusWithMessage(StatusCode::CONFLICT, anyhow!("upload is being written"))
+    })?;
+    uploads.remove(&id).await?;
     slog::info!(
         logger,
-        "CREATE";
-        "status" => 200,
-        "key" => &key,
-        "content-type" => content_type,
-        "content-length" => len,
-        "expiration" => %time::OffsetDateTime::from_unix_timestamp(expiration as i64)?,
+        "TERMINATE";
+        "status" => 204,
+        "upload" => &id,
     );
-    Ok(key)
+    Ok(tus_res()
+        .status(StatusCode::NO_CONTENT)
+        .body(Bytes::new().into())
+        .unwrap())
+}
+
+/// Calls `sweep` whenever the expiration it returns comes due, or sooner if `wake` is notified of
+/// a new one. Sleeps are capped at an hour so a jump in the wall clock can't stall cleanup.
+async fn schedule<F: FnMut() -> Fut, Fut: Future<Output = Option<u64>>>(
+    wake: Arc<Notify>,
+    mut sweep: F,
+) {
+    loop {
+        let delay = match sweep().await {
+            Some(next) => Duration::from_secs(next.saturating_sub(paste::now()))
+                .clamp(Duration::from_secs(1), HOUR),
+            None => HOUR,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = wake.notified() => (),
+        }
+    }
+}
+
+/// Removes expired sessions, returning when the next one expires.
+async fn clean_sessions(logger: Arc<slog::Logger>, sesh_tree: Sessions) -> Option<u64> {
+    let res = tokio::task::spawn_blocking(move || {
+        let expired = sesh_tree.expired(paste::now())?;
+        for session in &expired {
+            sesh_tree.remove(session)?;
+        }
+        Ok((expired.len(), sesh_tree.next_expiration()?))
+    })
+    .await
+    .map_err(Error::from)
+    .and_then(|res| res);
+    match res {
+        Ok((deleted, next)) => {
+            if deleted > 0 {
+                slog::info!(logger, "session cleaner complete"; "deleted" => deleted);
+            }
+            next
+        }
+        Err(e) => {
+            slog::error!(
+                logger,
+                "ERROR";
+                "context" => "session cleaner",
+                "reason" => %e,
+            );
+            None
+        }
+    }
+}
+
+/// Deletes expired pastes, returning when the next one expires.
+async fn clean_pastes(
+    logger: Arc<slog::Logger>,
+    pastes: Store,
+    receipts: Receipts,
+    downloads: Downloads,
+) -> Option<u64> {
+    let index = pastes.clone();
+    let expired = match tokio::task::spawn_blocking(move || index.expired(paste::now()))
+        .await
+        .map_err(Error::from)
+        .and_then(|res| res)
+    {
+        Ok(expired) => expired,
+        Err(e) => {
+            slog::error!(
+                logger,
+                "ERROR";
+                "context" => "expiration cleaner",
+                "reason" => %e,
+            );
+            return None;
+        }
+    };
+    let deleted = delete_pastes(
+        &logger,
+        &pastes,
+        &receipts,
+        &downloads,
+        expired,
+        "expiration cleaner",
+    )
+    .await;
+    if deleted > 0 {
+        slog::info!(logger, "expiration cleaner complete"; "deleted" => deleted);
+    }
+    let index = pastes.clone();
+    match tokio::task::spawn_blocking(move || index.next_expiration())
+        .await
+        .map_err(Error::from)
+        .and_then(|res| res)
+    {
+        Ok(next) => next,
+        Err(e) => {
+            slog::error!(
+                logger,
+                "ERROR";
+                "context" => "expiration cleaner",
+                "reason" => %e,
+            );
+            None
+        }
+    }
+}
+
+/// Drops receipts that have been kept long enough, returning when the next one is due.
+async fn clean_receipts(logger: Arc<slog::Logger>, receipts: Receipts) -> Option<u64> {
+    let res = tokio::task::spawn_blocking(move || {
+        let purged = receipts.purge(paste::now())?;
+        Ok((purged, receipts.next_expiration()?))
+    })
+    .await
+    .map_err(Error::from)
+    .and_then(|res| res);
+    match res {
+        Ok((purged, next)) => {
+            if purged > 0 {
+                slog::info!(logger, "receipt cleaner complete"; "deleted" => purged);
+            }
+            next
+        }
+        Err(e) => {
+            slog::error!(
+                logger,
+                "ERROR";
+                "context" => "receipt cleaner",
+                "reason" => %e,
+            );
+            None
+        }
+    }
+}
+
+/// Sends the webhooks that are due, returning when the next retry is.
+async fn deliver_webhooks(logger: Arc<slog::Logger>, webhooks: Webhooks) -> Option<u64> {
+    let res = async {
+        let (sent, failed) = webhooks.deliver(paste::now()).await?;
+        Ok::<_, Error>((sent, failed, webhooks.next_due()?))
+    }
+    .await;
+    match res {
+        Ok((sent, failed, next)) => {
+            if sent + failed > 0 {
+                slog::info!(logger, "webhooks delivered"; "sent" => sent, "failed" => failed);
+            }
+            next
+        }
+        Err(e) => {
+            slog::error!(
+                logger,
+                "ERROR";
+                "context" => "webhook delivery",
+                "reason" => %e,
+            );
+            None
+        }
+    }
+}
+
+/// Checks the free space on disk every so often, sending a low-disk webhook each time it drops
+/// below `threshold` bytes.
+async fn watch_disk(logger: Arc<slog::Logger>, webhooks: Webhooks, threshold: u64) {
+    let mut low = false;
+    loop {
+        match fs2::available_space(Path::new(".")) {
+            Ok(free) => {
+                if free < threshold && !low {
+                    slog::warn!(logger, "low disk space"; "free-space" => free);
+                    webhooks.low_disk(free, threshold, paste::now());
+                }
+                low = free < threshold;
+            }
+            Err(e) => slog::error!(
+                logger,
+                "ERROR";
+                "context" => "disk watcher",
+                "reason" => %e,
+            ),
+        }
+        tokio::time::sleep(DISK_CHECK_INTERVAL).await;
+    }
+}
+
+/// Discards unfinished uploads that have expired, returning when the next one expires. Uploads
+/// being written to are left for the next sweep.
+async fn clean_uploads(logger: Arc<slog::Logger>, uploads: Uploads) -> Option<u64> {
+    let res = async {
+        let mut deleted: usize = 0;
+        for id in uploads.expired(paste::now())? {
+            if let Some(_lock) = uploads.lock(&id) {
+                uploads.remove(&id).await?;
+                deleted += 1;
+            }
+        }
+        Ok::<_, Error>((deleted, uploads.next_expiration()?))
+    }
+    .await;
+    match res {
+        Ok((deleted, next)) => {
+            if deleted > 0 {
+                slog::info!(logger, "upload cleaner complete"; "deleted" => deleted);
+            }
+            next
+        }
+        Err(e) => {
+            slog::error!(
+                logger,
+                "ERROR";
+                "context" => "upload cleaner",
+                "reason" => %e,
+            );
+            None
+        }
+    }
 }
 
 #[derive(serde::Serialize)]
 struct NewDataRes {
     hash: String,
+    revocation_token: String,
+    /// The effective expiration, after applying the default lifetime.
+    expiration: u64,
+    /// Names the paste in webhook payloads, which never carry its key.
+    id: String,
+}
+
+const MIN_KEY_LENGTH: usize = 16;
+
+fn default_key_length() -> usize {
+    24
+}
+
+fn default_min_lifetime() -> u64 {
+    60
+}
+
+fn default_default_lifetime() -> u64 {
+    DAY.as_secs()
+}
+
+fn default_max_lifetime() -> u64 {
+    DAY.as_secs() * 30
+}
+
+fn default_max_paste_size() -> u64 {
+    1024
+}
+
+fn default_min_free_space() -> u64 {
+    512
+}
+
+fn default_download_grace() -> u64 {
+    HOUR.as_secs()
+}
+
+fn default_low_disk_warning() -> u64 {
+    2048
+}
+
+fn default_passphrase_attempts() -> u32 {
+    5
+}
+
+fn default_inbox_max_size() -> u64 {
+    16
+}
+
+fn default_inbox_rate_limit() -> u32 {
+    10
+}
+
+fn default_inbox_difficulty() -> u32 {
+    20
+}
+
+/// Each bit doubles the work, and 32 already takes billions of hashes.
+const MAX_INBOX_DIFFICULTY: u32 = 32;
+
+fn default_sqlite_path() -> PathBuf {
+    PathBuf::from("pastes.sqlite3")
 }
 
 #[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 struct Config {
     password: String,
+    #[serde(default = "default_key_length")]
+    key_length: usize,
+    #[serde(default = "default_min_lifetime")]
+    min_lifetime: u64,
+    #[serde(default = "default_default_lifetime")]
+    default_lifetime: u64,
+    #[serde(default = "default_max_lifetime")]
+    max_lifetime: u64,
+    /// MiB
+    #[serde(default = "default_max_paste_size")]
+    max_paste_size: u64,
+    /// MiB
+    #[serde(default)]
+    max_total_size: Option<u64>,
+    /// MiB
+    #[serde(default = "default_min_free_space")]
+    min_free_space: u64,
+    #[serde(default)]
+    storage: StorageBackend,
+    #[serde(default = "default_sqlite_path")]
+    sqlite_path: PathBuf,
+    #[serde(default)]
+    secure_erase: bool,
+    #[serde(default)]
+    durability: Durability,
+    /// seconds
+    #[serde(default = "default_download_grace")]
+    download_grace: u64,
+    #[serde(default)]
+    webhooks: Vec<webhook::Target>,
+    /// MiB
+    #[serde(default = "default_low_disk_warning")]
+    low_disk_warning: u64,
+    #[serde(default = "default_passphrase_attempts")]
+    passphrase_attempts: u32,
+    /// The age public key inbox messages are encrypted to. There is no inbox without one.
+    #[serde(default)]
+    inbox_recipient: Option<String>,
+    /// MiB
+    #[serde(default = "default_inbox_max_size")]
+    inbox_max_size: u64,
+    /// Messages per hour from each source.
+    #[serde(default = "default_inbox_rate_limit")]
+    inbox_rate_limit: u32,
+    /// Leading zero bits of the proof of work a source does to register.
+    #[serde(default = "default_inbox_difficulty")]
+    inbox_difficulty: u32,
 }
 
 #[derive(serde::Serialize)]
@@ -581,8 +3761,40 @@ pub enum Property {
     },
 }
 
+enum Command {
+    Serve,
+    /// Reports orphaned entries and blobs, fixing them with `--repair`, instead of serving.
+    Fsck {
+        repair: bool,
+    },
+}
+
+impl Command {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, AnyError> {
+        let command = match args.next().as_deref() {
+            None => Command::Serve,
+            Some("fsck") => match args.next().as_deref() {
+                None => Command::Fsck { repair: false },
+                Some("--repair") => Command::Fsck { repair: true },
+                Some(arg) => return Err(anyhow!("unexpected argument {}", arg)),
+            },
+            Some(arg) => {
+                return Err(anyhow!(
+                    "unknown command {}; usage: burn-after-reading [fsck [--repair]]",
+                    arg
+                ))
+            }
+        };
+        if let Some(arg) = args.next() {
+            return Err(anyhow!("unexpected argument {}", arg));
+        }
+        Ok(command)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), AnyError> {
+    let command = Command::parse(std::env::args().skip(1))?;
     let cfg: Config =
         serde_yaml::from_str(&tokio::fs::read_to_string("start9/config.yaml").await?)?;
     tokio::fs::write(
@@ -601,13 +3813,61 @@ async fn main() -> Result<(), AnyError> {
         })?,
     )
     .await?;
-    if let Ok(metadata) = tokio::fs::metadata("tmp").await {
-        if metadata.is_dir() {
-            tokio::fs::remove_dir_all("tmp").await?;
-        } else {
-            tokio::fs::remove_file("tmp").await?;
-        }
+    if cfg.key_length < MIN_KEY_LENGTH {
+        return Err(anyhow!(
+            "key-length must be at least {} bytes",
+            MIN_KEY_LENGTH
+        ));
+    }
+    let key_len = cfg.key_length;
+    if cfg.min_lifetime == 0
+        || cfg.min_lifetime > cfg.default_lifetime
+        || cfg.default_lifetime > cfg.max_lifetime
+    {
+        return Err(anyhow!(
+            "lifetimes must satisfy 0 < min-lifetime <= default-lifetime <= max-lifetime"
+        ));
+    }
+    if cfg.passphrase_attempts == 0 {
+        return Err(anyhow!("passphrase-attempts must be at least 1"));
+    }
+    let policy = PastePolicy {
+        key_len,
+        lifetimes: Lifetimes {
+            min: cfg.min_lifetime,
+            default: cfg.default_lifetime,
+            max: cfg.max_lifetime,
+        },
+        limits: Limits {
+            max_paste_size: cfg.max_paste_size * MIB,
+            max_total_size: cfg.max_total_size.map(|size| size * MIB),
+            min_free_space: cfg.min_free_space * MIB,
+        },
+        passphrase_attempts: cfg.passphrase_attempts,
+        inline: !(cfg.secure_erase && cfg.storage == StorageBackend::Sled),
+    };
+    if cfg.inbox_max_size == 0 || cfg.inbox_rate_limit == 0 {
+        return Err(anyhow!(
+            "inbox-max-size and inbox-rate-limit must be at least 1"
+        ));
+    }
+    if cfg.inbox_difficulty > MAX_INBOX_DIFFICULTY {
+        return Err(anyhow!(
+            "inbox-difficulty may be at most {}",
+            MAX_INBOX_DIFFICULTY
+        ));
     }
+    let inbox = match &cfg.inbox_recipient {
+        Some(recipient) => Some(Inbox::new(
+            recipient
+                .parse()
+                .map_err(|e| anyhow!("inbox-recipient: {}", e))?,
+            cfg.inbox_max_size * MIB,
+            cfg.inbox_rate_limit,
+            cfg.inbox_difficulty,
+        )),
+        None => None,
+    };
 
     let decorator = slog_term::TermDecorator::new().stderr().build();
     let drain = slog_term::FullFormat::new(decorator).build().fuse();
@@ -634,194 +3894,1148 @@ async fn main() -> Result<(), AnyError> {
     let sesh_cleaner_logger = logger.clone();
     let expiration_cleaner_logger = logger.clone();
     let data_logger = logger.clone();
+    let meta_logger = logger.clone();
+    let delete_logger = logger.clone();
+    let revoke_logger = logger.clone();
     let new_data_logger = logger.clone();
     let new_data_small_logger = logger.clone();
     let login_logger = logger.clone();
+    let receipt_cleaner_logger = logger.clone();
+    let webhook_logger = logger.clone();
+    let status_logger = logger.clone();
+    let statuses_logger = logger.clone();
+    let events_logger = logger.clone();
+    let list_logger = logger.clone();
+    let bulk_logger = logger.clone();
+    let new_bundle_logger = logger.clone();
+    let list_bundle_logger = logger.clone();
+    let bundle_logger = logger.clone();
+    let new_invite_logger = logger.clone();
+    let list_invites_logger = logger.clone();
+    let cancel_invite_logger = logger.clone();
+    let invited_data_logger = logger.clone();
+    let invite_cleaner_logger = logger.clone();
+    let inbox_key_logger = logger.clone();
+    let inbox_challenge_logger = logger.clone();
+    let new_source_logger = logger.clone();
+    let new_message_logger = logger.clone();
+    let list_inbox_logger = logger.clone();
+    let read_message_logger = logger.clone();
+    let burn_message_logger = logger.clone();
+    let list_users_logger = logger.clone();
+    let new_user_logger = logger.clone();
+    let update_user_logger = logger.clone();
+    let delete_user_logger = logger.clone();
+    let new_token_logger = logger.clone();
+    let list_tokens_logger = logger.clone();
+    let revoke_token_logger = logger.clone();
+    let token_delete_logger = logger.clone();
+    let token_data_small_logger = logger.clone();
+    let token_data_logger = logger.clone();
+    let upload_cleaner_logger = logger.clone();
+    let new_upload_logger = logger.clone();
+    let append_upload_logger = logger.clone();
+    let upload_offset_logger = logger.clone();
+    let terminate_upload_logger = logger.clone();
     let logout_logger = logger.clone();
 
-    let db = sled::open("burn-after-reading.db")?;
+    let db_path = Path::new("burn-after-reading.db");
+    if let Command::Fsck { repair } = command {
+        // holding the database also keeps a running server from touching the store meanwhile
+        let db = sled::open(db_path)?;
+        let pastes = store::open(
+            &logger,
+            cfg.storage,
+            &db,
+            cfg.sqlite_path,
+            cfg.secure_erase,
+            cfg.durability,
+        )
+        .await?;
+        let problems = store::fsck(&*pastes, repair)
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        return match (problems.len(), repair) {
+            (0, _) => Ok(()),
+            (n, true) => {
+                println!("repaired {} problems", n);
+                Ok(())
+            }
+            (n, false) => Err(anyhow!(
+                "found {} problems; run `burn-after-reading fsck --repair` to fix them",
+                n
+            )),
+        };
+    }
 
-    let pwd = Arc::new(cfg.password);
-    let sesh_tree = db.open_tree("sessions")?;
-    let sesh_tree_data = sesh_tree.clone();
-    let sesh_tree_data_small = sesh_tree.clone();
-    let sesh_tree_login = sesh_tree.clone();
-    let sesh_tree_cleaner = sesh_tree.clone();
-    tokio::spawn(async move {
-        loop {
-            let mut deleted: usize = 0;
-            for (session, expiration) in sesh_tree_cleaner.iter().filter_map(Result::ok) {
-                let mut exp = [0; 8];
-                exp.clone_from_slice(&expiration);
-                if SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-                    > u64::from_be_bytes(exp)
-                {
-                    if let Err(e) = sesh_tree_cleaner.remove(session) {
-                        slog::error!(
-                            sesh_cleaner_logger,
-                            "ERROR";
-                            "context" => "session cleaner",
-                            "reason" => %e,
-                        )
-                    };
-                    deleted += 1;
+    if let Ok(metadata) = tokio::fs::metadata("tmp").await {
+        if metadata.is_dir() {
+            if cfg.secure_erase {
+                if let Err(e) = store::overwrite_dir(Path::new("tmp")) {
+                    slog::error!(
+                        logger,
+                        "ERROR";
+                        "context" => "secure erase",
+                        "path" => "tmp",
+                        "reason" => %e,
+                    );
                 }
             }
-            slog::info!(sesh_cleaner_logger, "session cleaner complete"; "deleted" => deleted);
-            tokio::time::sleep(HOUR).await;
+            tokio::fs::remove_dir_all("tmp").await?;
+        } else {
+            tokio::fs::remove_file("tmp").await?;
         }
-    });
-    let data_tree = db.open_tree("data")?;
-    let new_data_tree = data_tree.clone();
-    let new_data_small_tree = data_tree.clone();
-    let data_tree_cleaner = data_tree.clone();
-    let content_type_tree = db.open_tree("content-type")?;
-    let new_content_type_tree = content_type_tree.clone();
-    let new_content_type_small_tree = content_type_tree.clone();
-    let content_type_tree_cleaner = content_type_tree.clone();
-    let expiration_tree = db.open_tree("expiration")?;
-    let new_expiration_tree = expiration_tree.clone();
-    let new_expiration_small_tree = expiration_tree.clone();
-    let expiration_tree_cleaner = expiration_tree.clone();
-    tokio::spawn(async move {
-        loop {
-            let mut deleted: usize = 0;
-            for (key, expiration) in expiration_tree_cleaner.iter().filter_map(Result::ok) {
-                let mut exp = [0; 8];
-                exp.clone_from_slice(&expiration);
-                if SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-                    > u64::from_be_bytes(exp)
-                {
-                    if let Err(e) = data(
-                        expiration_cleaner_logger.clone(),
-                        data_tree_cleaner.clone(),
-                        content_type_tree_cleaner.clone(),
-                        expiration_tree_cleaner.clone(),
-                        String::from_utf8(key.to_vec()).unwrap(),
-                        Method::DELETE,
-                    )
-                    .await
-                    {
-                        slog::error!(
-                            expiration_cleaner_logger,
-                            "ERROR";
-                            "context" => "expiration cleaner",
-                            "reason" => %e,
-                        )
-                    }
-                    deleted += 1;
-                }
-            }
-            slog::info!(expiration_cleaner_logger, "expiration cleaner complete"; "deleted" => deleted);
-            tokio::time::sleep(HOUR).await;
+    }
+
+    if cfg.secure_erase {
+        store::compact_sled(&logger, db_path)?;
+        slog::info!(logger, "compacted sled database");
+    }
+    let db = sled::open(db_path)?;
+
+    let sesh_tree = Sessions::open(&db)?;
+    let users = Users::open(&db)?;
+    {
+        let users = users.clone();
+        let password = cfg.password.clone();
+        let changed = tokio::task::spawn_blocking(move || users.bootstrap(&password, paste::now()))
+            .await?
+            .map_err(|e| anyhow!("{}", e))?;
+        if changed {
+            // sessions from before the password changed in the config
+            sesh_tree
+                .remove_user(user::BUILT_IN_ADMIN)
+                .map_err(|e| anyhow!("{}", e))?;
         }
-    });
+    }
+    let users_login = users.clone();
+    let users_delete = users.clone();
+    let users_list = users.clone();
+    let users_bulk = users.clone();
+    let users_list_inbox = users.clone();
+    let users_read_message = users.clone();
+    let users_burn_message = users.clone();
+    let users_list_users = users.clone();
+    let users_new_user = users.clone();
+    let users_update_user = users.clone();
+    let users_delete_user = users.clone();
+    let users_token_delete = users.clone();
+    let users_token_data_small = users.clone();
+    let users_uploads = users.clone();
+    let users_new_invite = users.clone();
+    let users_invited = users.clone();
+    let users_token_data = users;
+    let sesh_tree_data = sesh_tree.clone();
+    let sesh_tree_data_small = sesh_tree.clone();
+    let sesh_tree_login = sesh_tree.clone();
+    let sesh_tree_delete = sesh_tree.clone();
+    let sesh_tree_status = sesh_tree.clone();
+    let sesh_tree_statuses = sesh_tree.clone();
+    let sesh_tree_events = sesh_tree.clone();
+    let sesh_tree_list = sesh_tree.clone();
+    let sesh_tree_bulk = sesh_tree.clone();
+    let sesh_tree_bundle = sesh_tree.clone();
+    let sesh_tree_new_invite = sesh_tree.clone();
+    let sesh_tree_list_invites = sesh_tree.clone();
+    let sesh_tree_cancel_invite = sesh_tree.clone();
+    let sesh_tree_list_inbox = sesh_tree.clone();
+    let sesh_tree_read_message = sesh_tree.clone();
+    let sesh_tree_burn_message = sesh_tree.clone();
+    let sesh_tree_list_users = sesh_tree.clone();
+    let sesh_tree_new_user = sesh_tree.clone();
+    let sesh_tree_update_user = sesh_tree.clone();
+    let sesh_tree_delete_user = sesh_tree.clone();
+    let sesh_tree_new_token = sesh_tree.clone();
+    let sesh_tree_list_tokens = sesh_tree.clone();
+    let sesh_tree_revoke_token = sesh_tree.clone();
+    let sesh_tree_cleaner = sesh_tree.clone();
+    let sesh_tree_uploads = sesh_tree.clone();
+    let sesh_wake = Arc::new(Notify::new());
+    let sesh_wake_login = sesh_wake.clone();
+    tokio::spawn(schedule(sesh_wake, move || {
+        clean_sessions(sesh_cleaner_logger.clone(), sesh_tree_cleaner.clone())
+    }));
+    let pastes = store::open(
+        &logger,
+        cfg.storage,
+        &db,
+        cfg.sqlite_path,
+        cfg.secure_erase,
+        cfg.durability,
+    )
+    .await?;
+    let repaired = store::fsck(&*pastes, true)
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    for problem in &repaired {
+        slog::warn!(logger, "repaired store"; "problem" => %problem);
+    }
+    let webhooks = Webhooks::open(&db, cfg.webhooks, logger.clone())?;
+    let webhooks_sender = webhooks.clone();
+    tokio::spawn(schedule(webhooks.wake(), move || {
+        deliver_webhooks(webhook_logger.clone(), webhooks_sender.clone())
+    }));
+    tokio::spawn(watch_disk(
+        logger.clone(),
+        webhooks.clone(),
+        cfg.low_disk_warning * MIB,
+    ));
+    let receipts = Receipts::open(&db, webhooks, logger.clone())?;
+    let receipts_cleaner = receipts.clone();
+    let receipts_status = receipts.clone();
+    let receipts_statuses = receipts.clone();
+    let receipts_events = receipts.clone();
+    let receipts_delete = receipts.clone();
+    let receipts_revoke = receipts.clone();
+    let receipts_pastes_cleaner = receipts.clone();
+    let receipts_bulk = receipts.clone();
+    let receipts_new_data = receipts.clone();
+    let receipts_new_data_small = receipts.clone();
+    let receipts_new_bundle = receipts.clone();
+    let receipts_list_bundle = receipts.clone();
+    let receipts_bundle = receipts.clone();
+    let receipts_invited = receipts.clone();
+    let receipts_new_message = receipts.clone();
+    let receipts_burn_message = receipts.clone();
+    let receipts_token_delete = receipts.clone();
+    let receipts_token_data_small = receipts.clone();
+    let receipts_token_data = receipts.clone();
+    let receipts_uploads = receipts.clone();
+    tokio::spawn(schedule(Arc::new(Notify::new()), move || {
+        clean_receipts(receipt_cleaner_logger.clone(), receipts_cleaner.clone())
+    }));
+    let downloads = Downloads::new(
+        pastes.clone(),
+        receipts.clone(),
+        cfg.download_grace,
+        logger.clone(),
+    );
+    let resumed = downloads.resume().map_err(|e| anyhow!("{}", e))?;
+    if resumed > 0 {
+        slog::info!(logger, "resumed downloads"; "count" => resumed);
+    }
+    let downloads_cleaner = downloads.clone();
+    let downloads_delete = downloads.clone();
+    let downloads_revoke = downloads.clone();
+    let downloads_bulk = downloads.clone();
+    let downloads_list_bundle = downloads.clone();
+    let downloads_bundle = downloads.clone();
+    let downloads_read_message = downloads.clone();
+    let downloads_burn_message = downloads.clone();
+    let downloads_token_delete = downloads.clone();
+    let pastes_cleaner = pastes.clone();
+    let pastes_new_data = pastes.clone();
+    let pastes_new_data_small = pastes.clone();
+    let pastes_delete = pastes.clone();
+    let pastes_revoke = pastes.clone();
+    let pastes_meta = pastes.clone();
+    let pastes_status = pastes.clone();
+    let pastes_statuses = pastes.clone();
+    let pastes_list = pastes.clone();
+    let pastes_bulk = pastes.clone();
+    let pastes_new_bundle = pastes.clone();
+    let pastes_list_bundle = pastes.clone();
+    let pastes_bundle = pastes.clone();
+    let pastes_invited = pastes.clone();
+    let pastes_new_message = pastes.clone();
+    let pastes_list_inbox = pastes.clone();
+    let pastes_read_message = pastes.clone();
+    let pastes_burn_message = pastes.clone();
+    let pastes_token_delete = pastes.clone();
+    let pastes_token_data_small = pastes.clone();
+    let pastes_token_data = pastes.clone();
+    let pastes_uploads = pastes.clone();
+    let pastes_wake = Arc::new(Notify::new());
+    let pastes_wake_new_data = pastes_wake.clone();
+    let pastes_wake_new_data_small = pastes_wake.clone();
+    let pastes_wake_new_bundle = pastes_wake.clone();
+    let pastes_wake_invited = pastes_wake.clone();
+    let pastes_wake_new_message = pastes_wake.clone();
+    let pastes_wake_token_data_small = pastes_wake.clone();
+    let pastes_wake_token_data = pastes_wake.clone();
+    let pastes_wake_uploads = pastes_wake.clone();
+    tokio::spawn(schedule(pastes_wake, move || {
+        clean_pastes(
+            expiration_cleaner_logger.clone(),
+            pastes_cleaner.clone(),
+            receipts_pastes_cleaner.clone(),
+            downloads_cleaner.clone(),
+        )
+    }));
+    let uploads = Uploads::open(
+        &db,
+        "uploads",
+        cfg.secure_erase,
+        cfg.durability,
+        (*logger).clone(),
+    )?;
+    let (missing, orphans) = uploads.reconcile().await.map_err(|e| anyhow!("{}", e))?;
+    if missing + orphans > 0 {
+        slog::warn!(
+            logger,
+            "reconciled uploads";
+            "missing-files" => missing,
+            "orphaned-files" => orphans,
+        );
+    }
+    let uploads_cleaner = uploads.clone();
+    let uploads_wake = Arc::new(Notify::new());
+    let uploads_wake_new_upload = uploads_wake.clone();
+    tokio::spawn(schedule(uploads_wake, move || {
+        clean_uploads(upload_cleaner_logger.clone(), uploads_cleaner.clone())
+    }));
+    let invites = Invites::open(&db)?;
+    let invites_cleaner = invites.clone();
+    let invites_new = invites.clone();
+    let invites_list = invites.clone();
+    let invites_cancel = invites.clone();
+    let invites_invited = invites.clone();
+    let invites_delete_user = invites;
+    let invites_wake = Arc::new(Notify::new());
+    let invites_wake_new = invites_wake.clone();
+    tokio::spawn(schedule(invites_wake, move || {
+        clean_invites(invite_cleaner_logger.clone(), invites_cleaner.clone())
+    }));
+    let api_tokens = ApiTokens::open(&db)?;
+    let api_tokens_new = api_tokens.clone();
+    let api_tokens_list = api_tokens.clone();
+    let api_tokens_revoke = api_tokens.clone();
+    let api_tokens_delete_user = api_tokens.clone();
+    let api_tokens_delete = api_tokens.clone();
+    let api_tokens_data_small = api_tokens.clone();
+    let api_tokens_data = api_tokens;
     let filter = warp::filters::any::any()
         .and_then(|| async { Err::<Response<Body>, _>(warp::reject::reject()) })
+        .or(warp::path!("api" / "data" / String)
+            .and(warp::delete())
+            .and(warp::cookie("session"))
+            .and_then(move |key, session| {
+                let sesh_tree_delete = sesh_tree_delete.clone();
+                let users_delete = users_delete.clone();
+                let pastes_delete = pastes_delete.clone();
+                let receipts_delete = receipts_delete.clone();
+                let downloads_delete = downloads_delete.clone();
+                let delete_logger_clone = delete_logger.clone();
+                failable(delete_logger.clone(), "delete", move || {
+                    authenticate(sesh_tree_delete, session, move |user| {
+                        delete_paste(
+                            delete_logger_clone,
+                            users_delete,
+                            pastes_delete,
+                            receipts_delete,
+                            downloads_delete,
+                            key,
+                            user,
+                        )
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "data" / String)
+            .and(warp::delete())
+            .and(warp::header("authorization"))
+            .and_then(move |key, authorization| {
+                let api_tokens = api_tokens_delete.clone();
+                let users = users_token_delete.clone();
+                let pastes = pastes_token_delete.clone();
+                let receipts = receipts_token_delete.clone();
+                let downloads = downloads_token_delete.clone();
+                let logger = token_delete_logger.clone();
+                failable(token_delete_logger.clone(), "delete", move || {
+                    authenticate_token(
+                        api_tokens,
+                        users.clone(),
+                        authorization,
+                        move |user, scope| async move {
+                            if scope.create_only {
+                                return Err(Error::StatusWithMessage(
+                                    StatusCode::FORBIDDEN,
+                                    anyhow!("this token may only create pastes"),
+                                ));
+                            }
+                            delete_paste(logger, users, pastes, receipts, downloads, key, user)
+                                .await
+                        },
+                    )
+                })
+            }))
+        .or(warp::path!("api" / "data" / String)
+            .and(warp::delete())
+            .map(|_| unauthorized()))
+        .or(warp::path!("api" / "data" / String / "meta")
+            .and(warp::get())
+            .and_then(move |key| {
+                let pastes_meta = pastes_meta.clone();
+                let meta_logger_clone = meta_logger.clone();
+                failable(meta_logger.clone(), "meta", move || {
+                    meta(meta_logger_clone, pastes_meta, key)
+                })
+            }))
+        .or(warp::path!("api" / "data" / String / "meta").map(|_| method_not_allowed()))
         .or(warp::path!("api" / "data" / String)
             .and(warp::method())
-            .and_then(move |key, method| {
-                let data_tree = data_tree.clone();
-                let content_type_tree = content_type_tree.clone();
-                let expiration_tree = expiration_tree.clone();
+            .and(read_headers())
+            .and_then(move |key, method, read| {
+                let pastes = pastes.clone();
+                let receipts = receipts.clone();
+                let downloads = downloads.clone();
                 let data_logger_clone = data_logger.clone();
                 failable(data_logger.clone(), "data", move || {
                     data(
                         data_logger_clone.clone(),
-                        data_tree,
-                        content_type_tree,
-                        expiration_tree,
+                        pastes,
+                        receipts,
+                        downloads,
                         key,
                         method,
+                        read,
+                    )
+                })
+            }))
+        .or(warp::path!("api" / "revoke" / String)
+            .and(warp::delete())
+            .and_then(move |token| {
+                let pastes_revoke = pastes_revoke.clone();
+                let receipts_revoke = receipts_revoke.clone();
+                let downloads_revoke = downloads_revoke.clone();
+                let revoke_logger_clone = revoke_logger.clone();
+                failable(revoke_logger.clone(), "revoke", move || {
+                    revoke(
+                        revoke_logger_clone,
+                        pastes_revoke,
+                        receipts_revoke,
+                        downloads_revoke,
+                        token,
                     )
                 })
             }))
+        .or(warp::path!("api" / "revoke" / String).map(|_| method_not_allowed()))
+        .or(warp::path!("api" / "receipts")
+            .and(warp::get())
+            .and(warp::cookie("session"))
+            .and_then(move |session| {
+                let sesh_tree = sesh_tree_statuses.clone();
+                let pastes = pastes_statuses.clone();
+                let receipts = receipts_statuses.clone();
+                let logger = statuses_logger.clone();
+                failable(statuses_logger.clone(), "receipts", move || {
+                    authenticate(sesh_tree, session, move |user| {
+                        statuses(logger, pastes, receipts, user)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "receipts" / String)
+            .and(warp::get())
+            .and(warp::cookie("session"))
+            .and_then(move |key, session| {
+                let sesh_tree = sesh_tree_status.clone();
+                let pastes = pastes_status.clone();
+                let receipts = receipts_status.clone();
+                let logger = status_logger.clone();
+                failable(status_logger.clone(), "receipt", move || {
+                    authenticate(sesh_tree, session, move |user| {
+                        status(logger, pastes, receipts, user, key)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "events")
+            .and(warp::get())
+            .and(warp::cookie("session"))
+            .and_then(move |session| {
+                let sesh_tree = sesh_tree_events.clone();
+                let receipts = receipts_events.clone();
+                let logger = events_logger.clone();
+                failable(events_logger.clone(), "events", move || {
+                    authenticate(sesh_tree, session, move |user| {
+                        events(logger, receipts, user)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "receipts" / ..)
+            .and(warp::get())
+            .map(unauthorized))
+        .or(warp::path!("api" / "events")
+            .and(warp::get())
+            .map(unauthorized))
+        .or(warp::path!("api" / "receipts" / ..).map(method_not_allowed))
+        .or(warp::path!("api" / "events").map(method_not_allowed))
+        .or(warp::path!("api" / "pastes")
+            .and(warp::get())
+            .and(warp::cookie("session"))
+            .and(warp::query())
+            .and_then(move |session, query| {
+                let sesh_tree = sesh_tree_list.clone();
+                let users = users_list.clone();
+                let pastes = pastes_list.clone();
+                let logger = list_logger.clone();
+                failable(list_logger.clone(), "list pastes", move || {
+                    authenticate_admin(sesh_tree, users, session, move |_| {
+                        list_pastes(logger, pastes, query)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "pastes")
+            .and(warp::get())
+            .and(warp::cookie::<String>("session"))
+            .map(|_| bad_request("Invalid query")))
+        .or(warp::path!("api" / "pastes" / "bulk")
+            .and(warp::post())
+            .and(warp::cookie("session"))
+            .and(warp::body::content_length_limit(1_u64 << 20_u64))
+            .and(warp::body::bytes())
+            .and_then(move |session, body| {
+                let sesh_tree = sesh_tree_bulk.clone();
+                let users = users_bulk.clone();
+                let pastes = pastes_bulk.clone();
+                let receipts = receipts_bulk.clone();
+                let downloads = downloads_bulk.clone();
+                let logger = bulk_logger.clone();
+                failable(bulk_logger.clone(), "bulk action", move || {
+                    authenticate_admin(sesh_tree, users, session, move |_| {
+                        bulk_action(logger, pastes, receipts, downloads, body)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "pastes" / ..)
+            .and(warp::cookie::<String>("session"))
+            .map(|_| method_not_allowed()))
+        .or(warp::path!("api" / "pastes" / ..).map(unauthorized))
         .or(warp::path!("api" / "data")
             .and(warp::path::end())
             .and(warp::post())
             .and(warp::cookie("session"))
-            .and(warp::header("content-type"))
-            .and(warp::header::optional("x-paste-expiration"))
+            .and(new_paste_headers())
             .and(warp::body::content_length_limit(1_u64 << 20_u64))
             .and(warp::body::bytes())
-            .and_then(
-                move |session, content_type, expiration: Option<u64>, body| {
-                    let sesh_tree_data_small = sesh_tree_data_small.clone();
-                    let new_data_small_tree = new_data_small_tree.clone();
-                    let new_content_type_small_tree = new_content_type_small_tree.clone();
-                    let new_expiration_small_tree = new_expiration_small_tree.clone();
-                    let new_data_small_logger_clone = new_data_small_logger.clone();
-                    failable(new_data_small_logger.clone(), "new data small", move || {
-                        authenticate(sesh_tree_data_small, session, move |_| {
-                            new_data_small(
-                                new_data_small_logger_clone.clone(),
-                                new_data_small_tree,
-                                new_content_type_small_tree,
-                                new_expiration_small_tree,
-                                content_type,
-                                expiration.unwrap_or_else(|| {
-                                    (SystemTime::now()
-                                        .duration_since(UNIX_EPOCH)
-                                        .unwrap_or(Duration::from_secs(0))
-                                        + DAY)
-                                        .as_secs()
-                                }),
-                                body,
-                            )
-                        })
-                        .map_ok(|hash| ok_json(&NewDataRes { hash }))
+            .and_then(move |session, paste, body| {
+                let sesh_tree_data_small = sesh_tree_data_small.clone();
+                let pastes_new_data_small = pastes_new_data_small.clone();
+                let receipts_new_data_small = receipts_new_data_small.clone();
+                let pastes_wake_new_data_small = pastes_wake_new_data_small.clone();
+                let new_data_small_logger_clone = new_data_small_logger.clone();
+                failable(new_data_small_logger.clone(), "new data small", move || {
+                    authenticate(sesh_tree_data_small, session, move |user| {
+                        new_data_small(
+                            new_data_small_logger_clone.clone(),
+                            pastes_new_data_small,
+                            receipts_new_data_small,
+                            policy,
+                            paste,
+                            body,
+                            user,
+                        )
                     })
-                },
-            ));
+                    .map_ok(move |res| {
+                        pastes_wake_new_data_small.notify_one();
+                        ok_json(&res)
+                    })
+                })
+            }))
+        // boxed so the routes chained on below don't nest deep enough to overflow the stack
+        .boxed();
     #[cfg(not(feature = "demo"))]
     let filter = filter.or(warp::path!("api" / "data")
         .and(warp::path::end())
         .and(warp::post())
         .and(warp::cookie("session"))
-        .and(warp::header("content-type"))
-        .and(warp::header::optional("x-paste-expiration"))
+        .and(new_paste_headers())
+        .and(warp::body::stream())
+        .and_then(move |session, paste, body| {
+            let sesh_tree_data = sesh_tree_data.clone();
+            let pastes_new_data = pastes_new_data.clone();
+            let receipts_new_data = receipts_new_data.clone();
+            let pastes_wake_new_data = pastes_wake_new_data.clone();
+            let new_data_logger_clone = new_data_logger.clone();
+            failable(new_data_logger.clone(), "new data", move || {
+                authenticate(sesh_tree_data, session, move |user| {
+                    new_data(
+                        new_data_logger_clone.clone(),
+                        pastes_new_data,
+                        receipts_new_data,
+                        policy,
+                        paste,
+                        body,
+                        user,
+                    )
+                })
+                .map_ok(move |res| {
+                    pastes_wake_new_data.notify_one();
+                    ok_json(&res)
+                })
+            })
+        }));
+    #[cfg(not(feature = "demo"))]
+    let filter = {
+        let uploads_new = uploads.clone();
+        let uploads_offset = uploads.clone();
+        let uploads_append = uploads.clone();
+        let uploads_terminate = uploads;
+        let sesh_tree_new_upload = sesh_tree_uploads.clone();
+        let sesh_tree_upload_offset = sesh_tree_uploads.clone();
+        let sesh_tree_append_upload = sesh_tree_uploads.clone();
+        let sesh_tree_terminate_upload = sesh_tree_uploads;
+        let users_upload_offset = users_uploads.clone();
+        let users_append_upload = users_uploads.clone();
+        let users_terminate_upload = users_uploads;
+        let pastes_new_upload = pastes_uploads.clone();
+        let pastes_append_upload = pastes_uploads;
+        let receipts_append_upload = receipts_uploads;
+        let pastes_wake_append_upload = pastes_wake_uploads;
+        filter
+            .or(warp::path!("api" / "uploads" / ..)
+                .and(warp::options())
+                .map(move || tus_options(policy)))
+            .or(warp::path!("api" / "uploads")
+                .and(warp::post())
+                .and(warp::cookie("session"))
+                .and(new_upload_headers())
+                .and_then(move |session, new| {
+                    let sesh_tree = sesh_tree_new_upload.clone();
+                    let uploads = uploads_new.clone();
+                    let pastes = pastes_new_upload.clone();
+                    let uploads_wake = uploads_wake_new_upload.clone();
+                    let logger = new_upload_logger.clone();
+                    failable(new_upload_logger.clone(), "new upload", move || {
+                        authenticate(sesh_tree, session, move |user| {
+                            new_upload(logger, uploads, pastes, policy, new, user)
+                        })
+                        .map_ok(move |res| {
+                            uploads_wake.notify_one();
+                            res
+                        })
+                    })
+                }))
+            .or(warp::path!("api" / "uploads" / String)
+                .and(warp::head())
+                .and(warp::cookie("session"))
+                .and(warp::header::optional("tus-resumable"))
+                .and_then(move |id, session, tus_resumable| {
+                    let sesh_tree = sesh_tree_upload_offset.clone();
+                    let uploads = uploads_offset.clone();
+                    let users = users_upload_offset.clone();
+                    failable(upload_offset_logger.clone(), "upload offset", move || {
+                        authenticate(sesh_tree, session, move |user| {
+                            upload_offset(uploads, users, id, tus_resumable, user)
+                        })
+                    })
+                }))
+            .or(warp::path!("api" / "uploads" / String)
+                .and(warp::patch())
+                .and(warp::cookie("session"))
+                .and(upload_chunk_headers())
+                .and(warp::body::stream())
+                .and_then(move |id, session, chunk, body| {
+                    let sesh_tree = sesh_tree_append_upload.clone();
+                    let uploads = uploads_append.clone();
+                    let users = users_append_upload.clone();
+                    let pastes = pastes_append_upload.clone();
+                    let receipts = receipts_append_upload.clone();
+                    let pastes_wake = pastes_wake_append_upload.clone();
+                    let logger = append_upload_logger.clone();
+                    failable(append_upload_logger.clone(), "append upload", move || {
+                        authenticate(sesh_tree, session, move |user| {
+                            append_upload(
+                                logger, uploads, users, pastes, receipts, policy, id, chunk, body,
+                                user,
+                            )
+                        })
+                        .map_ok(move |res| {
+                            pastes_wake.notify_one();
+                            res
+                        })
+                    })
+                }))
+            .or(warp::path!("api" / "uploads" / String)
+                .and(warp::delete())
+                .and(warp::cookie("session"))
+                .and(warp::header::optional("tus-resumable"))
+                .and_then(move |id, session, tus_resumable| {
+                    let sesh_tree = sesh_tree_terminate_upload.clone();
+                    let uploads = uploads_terminate.clone();
+                    let users = users_terminate_upload.clone();
+                    let logger = terminate_upload_logger.clone();
+                    failable(
+                        terminate_upload_logger.clone(),
+                        "terminate upload",
+                        move || {
+                            authenticate(sesh_tree, session, move |user| {
+                                terminate_upload(logger, uploads, users, id, tus_resumable, user)
+                            })
+                        },
+                    )
+                }))
+            .or(warp::path!("api" / "uploads" / ..)
+                .and(warp::cookie::<String>("session"))
+                .map(|_| method_not_allowed()))
+            .or(warp::path!("api" / "uploads" / ..).map(unauthorized))
+    };
+    #[cfg(not(feature = "demo"))]
+    let filter = filter.or(warp::path!("api" / "bundles")
+        .and(warp::post())
+        .and(warp::cookie("session"))
+        .and(new_paste_headers())
+        .and(warp::multipart::form().max_length(policy.limits.max_paste_size + MIB))
+        .and_then(move |session, paste, form| {
+            let sesh_tree = sesh_tree_bundle.clone();
+            let pastes = pastes_new_bundle.clone();
+            let receipts = receipts_new_bundle.clone();
+            let pastes_wake = pastes_wake_new_bundle.clone();
+            let logger = new_bundle_logger.clone();
+            failable(new_bundle_logger.clone(), "new bundle", move || {
+                authenticate(sesh_tree, session, move |user| {
+                    new_bundle(logger, pastes, receipts, policy, paste, form, user)
+                })
+                .map_ok(move |res| {
+                    pastes_wake.notify_one();
+                    ok_json(&res)
+                })
+            })
+        }));
+    let filter = filter
+        .or(warp::path!("api" / "bundles" / String)
+            .and(warp::get())
+            .and(warp::header::optional("x-paste-passphrase"))
+            .and_then(move |key, passphrase| {
+                let pastes = pastes_list_bundle.clone();
+                let receipts = receipts_list_bundle.clone();
+                let downloads = downloads_list_bundle.clone();
+                let logger = list_bundle_logger.clone();
+                failable(list_bundle_logger.clone(), "list bundle", move || {
+                    list_bundle(logger, pastes, receipts, downloads, key, passphrase)
+                })
+            })
+            .or(warp::path!("api" / "bundles" / String / BundlePart)
+                .and(warp::get())
+                .and(read_headers())
+                .and_then(move |key, part, read| {
+                    let pastes = pastes_bundle.clone();
+                    let receipts = receipts_bundle.clone();
+                    let downloads = downloads_bundle.clone();
+                    let logger = bundle_logger.clone();
+                    failable(bundle_logger.clone(), "bundle", move || {
+                        bundle(logger, pastes, receipts, downloads, key, part, read)
+                    })
+                }))
+            .or(warp::path!("api" / "bundles")
+                .and(warp::post())
+                .and(warp::cookie::<String>("session"))
+                .map(|_| bad_request("Content-Type must be multipart/form-data")))
+            .or(warp::path!("api" / "bundles")
+                .and(warp::post())
+                .map(unauthorized))
+            .or(warp::path!("api" / "bundles" / ..)
+                .and(warp::get())
+                .map(not_found))
+            .or(warp::path!("api" / "bundles" / ..).map(method_not_allowed)))
+        // boxed again for the same reason as the routes above
+        .boxed();
+    #[cfg(not(feature = "demo"))]
+    let filter = filter.or(warp::path!("api" / "invites" / String)
+        .and(warp::post())
+        .and(new_paste_headers())
+        .and(warp::body::stream())
+        .and_then(move |token, paste, body| {
+            let invites = invites_invited.clone();
+            let users = users_invited.clone();
+            let pastes = pastes_invited.clone();
+            let receipts = receipts_invited.clone();
+            let pastes_wake = pastes_wake_invited.clone();
+            let logger = invited_data_logger.clone();
+            failable(invited_data_logger.clone(), "invited data", move || {
+                invited_data(
+                    logger, invites, users, pastes, receipts, policy, token, paste, body,
+                )
+                .map_ok(move |res| {
+                    pastes_wake.notify_one();
+                    res
+                })
+            })
+        }));
+    let filter = filter
+        .or(warp::path!("api" / "invites")
+            .and(warp::post())
+            .and(warp::cookie("session"))
+            .and(warp::body::content_length_limit(1_u64 << 20_u64))
+            .and(warp::body::bytes())
+            .and_then(move |session, body| {
+                let sesh_tree = sesh_tree_new_invite.clone();
+                let users = users_new_invite.clone();
+                let invites = invites_new.clone();
+                let invites_wake = invites_wake_new.clone();
+                let logger = new_invite_logger.clone();
+                failable(new_invite_logger.clone(), "new invite", move || {
+                    authenticate_admin(sesh_tree, users, session, move |user| {
+                        new_invite(logger, invites, policy, body, user)
+                    })
+                    .map_ok(move |res| {
+                        invites_wake.notify_one();
+                        res
+                    })
+                })
+            })
+            .or(warp::path!("api" / "invites")
+                .and(warp::get())
+                .and(warp::cookie("session"))
+                .and_then(move |session| {
+                    let sesh_tree = sesh_tree_list_invites.clone();
+                    let invites = invites_list.clone();
+                    let logger = list_invites_logger.clone();
+                    failable(list_invites_logger.clone(), "list invites", move || {
+                        authenticate(sesh_tree, session, move |user| {
+                            list_invites(logger, invites, user)
+                        })
+                    })
+                }))
+            .or(warp::path!("api" / "invites" / String)
+                .and(warp::delete())
+                .and(warp::cookie("session"))
+                .and_then(move |id, session| {
+                    let sesh_tree = sesh_tree_cancel_invite.clone();
+                    let invites = invites_cancel.clone();
+                    let logger = cancel_invite_logger.clone();
+                    failable(cancel_invite_logger.clone(), "cancel invite", move || {
+                        authenticate(sesh_tree, session, move |user| {
+                            cancel_invite(logger, invites, user, id)
+                        })
+                    })
+                }))
+            .or(warp::path!("api" / "invites" / String)
+                .and(warp::post())
+                .map(|_| bad_request("Missing Content-Type")))
+            .or(warp::path!("api" / "invites" / ..)
+                .and(warp::cookie::<String>("session"))
+                .map(|_| method_not_allowed()))
+            .or(warp::path!("api" / "invites").map(unauthorized))
+            .or(warp::path!("api" / "invites" / String)
+                .and(warp::delete())
+                .map(|_| unauthorized()))
+            .or(warp::path!("api" / "invites" / ..).map(method_not_allowed)))
+        .boxed();
+    let inbox_new_message = inbox.clone();
+    let inbox_challenge_route = inbox.clone();
+    let inbox_new_source = inbox.clone();
+    let filter = filter
+        .or(warp::path!("api" / "inbox" / "key")
+            .and(warp::get())
+            .and_then(move || {
+                let inbox = inbox.clone();
+                let logger = inbox_key_logger.clone();
+                failable(inbox_key_logger.clone(), "inbox key", move || {
+                    inbox_key(logger, inbox)
+                })
+            }))
+        .or(warp::path!("api" / "inbox" / "challenge")
+            .and(warp::get())
+            .and_then(move || {
+                let inbox = inbox_challenge_route.clone();
+                let logger = inbox_challenge_logger.clone();
+                failable(
+                    inbox_challenge_logger.clone(),
+                    "inbox challenge",
+                    move || inbox_challenge(logger, inbox),
+                )
+            }));
+    #[cfg(not(feature = "demo"))]
+    let filter = filter
+        .or(warp::path!("api" / "inbox" / "sources")
+            .and(warp::post())
+            .and(warp::header("x-inbox-stamp"))
+            .and_then(move |stamp| {
+                let inbox = inbox_new_source.clone();
+                let logger = new_source_logger.clone();
+                failable(new_source_logger.clone(), "new source", move || {
+                    new_source(logger, inbox, stamp)
+                })
+            }))
+        .or(warp::path!("api" / "inbox" / "sources")
+            .and(warp::post())
+            .map(|| bad_request("Missing x-inbox-stamp")));
+    #[cfg(not(feature = "demo"))]
+    let filter = filter.or(warp::path!("api" / "inbox")
+        .and(warp::post())
+        .and(warp::header::optional("x-inbox-source"))
+        .and(warp::header::optional("content-type"))
+        .and(warp::header::optional("content-length"))
         .and(warp::body::stream())
-        .and_then(
-            move |session, content_type, expiration: Option<u64>, body| {
-                let sesh_tree_data = sesh_tree_data.clone();
-                let new_data_tree = new_data_tree.clone();
-                let new_content_type_tree = new_content_type_tree.clone();
-                let new_expiration_tree = new_expiration_tree.clone();
-                let new_data_logger_clone = new_data_logger.clone();
-                failable(new_data_logger.clone(), "new data", move || {
-                    authenticate(sesh_tree_data, session, move |_| {
+        .and_then(move |source, content_type, content_length, body| {
+            let pastes = pastes_new_message.clone();
+            let receipts = receipts_new_message.clone();
+            let inbox = inbox_new_message.clone();
+            let pastes_wake = pastes_wake_new_message.clone();
+            let logger = new_message_logger.clone();
+            failable(new_message_logger.clone(), "new message", move || {
+                new_message(
+                    logger,
+                    pastes,
+                    receipts,
+                    policy,
+                    inbox,
+                    source,
+                    content_type,
+                    content_length,
+                    body,
+                )
+                .map_ok(move |res| {
+                    pastes_wake.notify_one();
+                    res
+                })
+            })
+        }));
+    let filter = filter
+        .or(warp::path!("api" / "inbox")
+            .and(warp::get())
+            .and(warp::cookie("session"))
+            .and_then(move |session| {
+                let sesh_tree = sesh_tree_list_inbox.clone();
+                let users = users_list_inbox.clone();
+                let pastes = pastes_list_inbox.clone();
+                let logger = list_inbox_logger.clone();
+                failable(list_inbox_logger.clone(), "list inbox", move || {
+                    authenticate_admin(sesh_tree, users, session, move |_| {
+                        list_inbox(logger, pastes)
+                    })
+                })
+            })
+            .or(warp::path!("api" / "inbox" / String)
+                .and(warp::get())
+                .and(warp::cookie("session"))
+                .and(read_headers())
+                .and_then(move |key, session, read| {
+                    let sesh_tree = sesh_tree_read_message.clone();
+                    let users = users_read_message.clone();
+                    let pastes = pastes_read_message.clone();
+                    let downloads = downloads_read_message.clone();
+                    let logger = read_message_logger.clone();
+                    failable(read_message_logger.clone(), "read message", move || {
+                        authenticate_admin(sesh_tree, users, session, move |_| {
+                            read_message(logger, pastes, downloads, key, read)
+                        })
+                    })
+                }))
+            .or(warp::path!("api" / "inbox" / String)
+                .and(warp::delete())
+                .and(warp::cookie("session"))
+                .and_then(move |key, session| {
+                    let sesh_tree = sesh_tree_burn_message.clone();
+                    let users = users_burn_message.clone();
+                    let pastes = pastes_burn_message.clone();
+                    let receipts = receipts_burn_message.clone();
+                    let downloads = downloads_burn_message.clone();
+                    let logger = burn_message_logger.clone();
+                    failable(burn_message_logger.clone(), "burn message", move || {
+                        authenticate_admin(sesh_tree, users, session, move |_| {
+                            burn_message(logger, pastes, receipts, downloads, key)
+                        })
+                    })
+                }))
+            .or(warp::path!("api" / "inbox" / ..)
+                .and(warp::cookie::<String>("session"))
+                .map(|_| method_not_allowed()))
+            .or(warp::path!("api" / "inbox")
+                .and(warp::get())
+                .map(unauthorized))
+            .or(warp::path!("api" / "inbox" / String)
+                .and(warp::get().or(warp::delete()).unify())
+                .map(|_| unauthorized()))
+            .or(warp::path!("api" / "inbox" / ..).map(method_not_allowed)))
+        .boxed();
+    let filter = filter
+        .or(warp::path!("api" / "users")
+            .and(warp::get())
+            .and(warp::cookie("session"))
+            .and_then(move |session| {
+                let sesh_tree = sesh_tree_list_users.clone();
+                let users = users_list_users.clone();
+                let logger = list_users_logger.clone();
+                failable(list_users_logger.clone(), "list users", move || {
+                    authenticate_admin(sesh_tree, users.clone(), session, move |_| {
+                        list_users(logger, users)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "users")
+            .and(warp::post())
+            .and(warp::cookie("session"))
+            .and(warp::body::content_length_limit(1_u64 << 20_u64))
+            .and(warp::body::bytes())
+            .and_then(move |session, body| {
+                let sesh_tree = sesh_tree_new_user.clone();
+                let users = users_new_user.clone();
+                let logger = new_user_logger.clone();
+                failable(new_user_logger.clone(), "new user", move || {
+                    authenticate_admin(sesh_tree, users.clone(), session, move |admin| {
+                        new_user(logger, users, body, admin)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "users" / String)
+            .and(warp::patch())
+            .and(warp::cookie("session"))
+            .and(warp::body::content_length_limit(1_u64 << 20_u64))
+            .and(warp::body::bytes())
+            .and_then(move |name, session, body| {
+                let sesh_tree = sesh_tree_update_user.clone();
+                let users = users_update_user.clone();
+                let logger = update_user_logger.clone();
+                failable(update_user_logger.clone(), "update user", move || {
+                    authenticate_admin(sesh_tree.clone(), users.clone(), session, move |admin| {
+                        update_user(logger, users, sesh_tree, name, body, admin)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "users" / String)
+            .and(warp::delete())
+            .and(warp::cookie("session"))
+            .and_then(move |name, session| {
+                let sesh_tree = sesh_tree_delete_user.clone();
+                let users = users_delete_user.clone();
+                let invites = invites_delete_user.clone();
+                let api_tokens = api_tokens_delete_user.clone();
+                let logger = delete_user_logger.clone();
+                failable(delete_user_logger.clone(), "delete user", move || {
+                    authenticate_admin(sesh_tree.clone(), users.clone(), session, move |admin| {
+                        delete_user(logger, users, sesh_tree, invites, api_tokens, name, admin)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "users" / ..)
+            .and(warp::cookie::<String>("session"))
+            .map(|_| method_not_allowed()))
+        .or(warp::path!("api" / "users" / ..).map(unauthorized))
+        .boxed();
+    let filter = filter
+        .or(warp::path!("api" / "tokens")
+            .and(warp::post())
+            .and(warp::cookie("session"))
+            .and(warp::body::content_length_limit(1_u64 << 20_u64))
+            .and(warp::body::bytes())
+            .and_then(move |session, body| {
+                let sesh_tree = sesh_tree_new_token.clone();
+                let api_tokens = api_tokens_new.clone();
+                let logger = new_token_logger.clone();
+                failable(new_token_logger.clone(), "new token", move || {
+                    authenticate(sesh_tree, session, move |user| {
+                        new_api_token(logger, api_tokens, policy, body, user)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "tokens")
+            .and(warp::get())
+            .and(warp::cookie("session"))
+            .and_then(move |session| {
+                let sesh_tree = sesh_tree_list_tokens.clone();
+                let api_tokens = api_tokens_list.clone();
+                let logger = list_tokens_logger.clone();
+                failable(list_tokens_logger.clone(), "list tokens", move || {
+                    authenticate(sesh_tree, session, move |user| {
+                        list_api_tokens(logger, api_tokens, user)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "tokens" / String)
+            .and(warp::delete())
+            .and(warp::cookie("session"))
+            .and_then(move |id, session| {
+                let sesh_tree = sesh_tree_revoke_token.clone();
+                let api_tokens = api_tokens_revoke.clone();
+                let logger = revoke_token_logger.clone();
+                failable(revoke_token_logger.clone(), "revoke token", move || {
+                    authenticate(sesh_tree, session, move |user| {
+                        revoke_api_token(logger, api_tokens, user, id)
+                    })
+                })
+            }))
+        .or(warp::path!("api" / "tokens" / ..)
+            .and(warp::cookie::<String>("session"))
+            .map(|_| method_not_allowed()))
+        .or(warp::path!("api" / "tokens" / ..).map(unauthorized))
+        .or(warp::path!("api" / "data")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::header("authorization"))
+            .and(new_paste_headers())
+            .and(warp::body::content_length_limit(1_u64 << 20_u64))
+            .and(warp::body::bytes())
+            .and_then(move |authorization, paste, body| {
+                let api_tokens = api_tokens_data_small.clone();
+                let users = users_token_data_small.clone();
+                let pastes = pastes_token_data_small.clone();
+                let receipts = receipts_token_data_small.clone();
+                let pastes_wake = pastes_wake_token_data_small.clone();
+                let logger = token_data_small_logger.clone();
+                failable(
+                    token_data_small_logger.clone(),
+                    "new data small",
+                    move || {
+                        authenticate_token(api_tokens, users, authorization, move |user, scope| {
+                            new_data_small(
+                                logger,
+                                pastes,
+                                receipts,
+                                policy.scoped(&scope),
+                                paste,
+                                body,
+                                user,
+                            )
+                        })
+                        .map_ok(move |res| {
+                            pastes_wake.notify_one();
+                            ok_json(&res)
+                        })
+                    },
+                )
+            }))
+        // every route so far answers in a response of its own nested type; flattening them keeps
+        // the type of the whole filter within the compiler's recursion limit
+        .map(warp::Reply::into_response)
+        .boxed();
+    #[cfg(not(feature = "demo"))]
+    let filter = filter
+        .or(warp::path!("api" / "data")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::header("authorization"))
+            .and(new_paste_headers())
+            .and(warp::body::stream())
+            .and_then(move |authorization, paste, body| {
+                let api_tokens = api_tokens_data.clone();
+                let users = users_token_data.clone();
+                let pastes = pastes_token_data.clone();
+                let receipts = receipts_token_data.clone();
+                let pastes_wake = pastes_wake_token_data.clone();
+                let logger = token_data_logger.clone();
+                failable(token_data_logger.clone(), "new data", move || {
+                    authenticate_token(api_tokens, users, authorization, move |user, scope| {
                         new_data(
-                            new_data_logger_clone.clone(),
-                            new_data_tree,
-                            new_content_type_tree,
-                            new_expiration_tree,
-                            content_type,
-                            expiration.unwrap_or_else(|| {
-                                (SystemTime::now()
-                                    .duration_since(UNIX_EPOCH)
-                                    .unwrap_or(Duration::from_secs(0))
-                                    + DAY)
-                                    .as_secs()
-                            }),
+                            logger,
+                            pastes,
+                            receipts,
+                            policy.scoped(&scope),
+                            paste,
                             body,
+                            user,
                         )
                     })
-                    .map_ok(|hash| ok_json(&NewDataRes { hash }))
+                    .map_ok(move |res| {
+                        pastes_wake.notify_one();
+                        ok_json(&res)
+                    })
                 })
-            },
-        ));
+            }))
+        .boxed();
     let filter = filter
         .or(warp::path!("api" / "data")
             .and(warp::path::end())
             .and(warp::post())
             .and(warp::cookie::<String>("session"))
             .map(|_| bad_request("Missing Content-Type")))
+        .or(warp::path!("api" / "data")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::header::<String>("authorization"))
+            .map(|_| bad_request("Missing Content-Type")))
         .or(warp::path!("api" / "data")
             .and(warp::path::end())
             .and(warp::post())
@@ -834,10 +5048,14 @@ async fn main() -> Result<(), AnyError> {
             .and(warp::post())
             .and(warp::body::json())
             .and_then(move |login_info| {
-                let pwd = pwd.clone();
+                let users = users_login.clone();
                 let sesh_tree = sesh_tree.clone();
+                let sesh_wake_login = sesh_wake_login.clone();
                 failable(login_logger.clone(), "login", move || {
-                    login(pwd, sesh_tree, login_info)
+                    login(users, sesh_tree, login_info).map_ok(move |res| {
+                        sesh_wake_login.notify_one();
+                        res
+                    })
                 })
             }))
         .or(warp::path!("api" / "login")
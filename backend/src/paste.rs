@@ -0,0 +1,293 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Error as AnyError};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use hyper::body::Bytes;
+
+/// Where the body of a paste is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Storage {
+    /// Alongside the record in the paste store.
+    Inline,
+    /// In the blob store, under the paste key.
+    Big,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PasteOptions {
+    pub max_views: u64,
+}
+
+/// Everything known about a paste except its body.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PasteRecord {
+    pub storage: Storage,
+    pub content_type: String,
+    /// Unix timestamp after which the paste is no longer served.
+    pub expiration: u64,
+    /// Unix timestamp of creation. Pastes migrated from the per-field tree layout carry the time
+    /// of migration instead.
+    pub created_at: u64,
+    pub size: u64,
+    pub options: PasteOptions,
+    pub remaining_views: u64,
+    /// SHA-256 of the revocation token handed out at creation.
+    pub revocation_hash: Option<Vec<u8>>,
+    /// Unix timestamp at which the download that will take the next view of a
+    /// [`Storage::Big`] paste began. Cleared once that view is taken.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloading_since: Option<u64>,
+    /// SHA-256 of the token handed to the request that began that download. Only requests
+    /// carrying the token may continue it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_token_hash: Option<Vec<u8>>,
+    /// The user who created the paste. Pastes from before ownership was recorded have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Names the paste to its creator without giving away its key, as in webhook payloads.
+    /// Pastes from before these were handed out have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// A note from the creator saying what the paste is, shown in the admin listing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Unix timestamp before which the paste exists but can't be read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<u64>,
+    /// The files of a bundle, stored back to back in its blob. A bundle is a [`Storage::Big`]
+    /// paste whose view is taken once every file has been downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<Vec<BundleEntry>>,
+    /// Checked by the server before any of the body is served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<Passphrase>,
+    /// Set on messages left in the inbox, which are only ever served to a logged-in user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbox: Option<InboxMessage>,
+}
+
+/// What is known about an inbox message without decrypting it.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InboxMessage {
+    /// As declared by the source. The stored body is always age ciphertext.
+    pub content_type: String,
+    /// Whether the source sent an age file of its own, rather than having the server encrypt
+    /// the message on arrival.
+    pub encrypted_by_source: bool,
+}
+
+/// An access passphrase, and the wrong guesses made at it so far.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Passphrase {
+    /// Argon2id hash, as a PHC string.
+    pub hash: String,
+    /// Wrong guesses allowed before the paste burns.
+    pub max_attempts: u32,
+    pub failed_attempts: u32,
+}
+
+/// Hashes `secret` with Argon2id and a fresh salt, returning the PHC string. Slow on purpose, so
+/// best kept off the runtime.
+pub fn hash_secret(secret: &str) -> Result<String, AnyError> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    let hash = Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .map_err(|e| anyhow!("hashing: {}", e))?;
+    Ok(hash.to_string())
+}
+
+/// Whether `guess` matches a hash made by [`hash_secret`]. As slow as hashing it.
+pub fn verify_secret(hash: &str, guess: &str) -> Result<bool, AnyError> {
+    let hash = PasswordHash::new(hash).map_err(|e| anyhow!("parsing hash: {}", e))?;
+    match Argon2::default().verify_password(guess.as_bytes(), &hash) {
+        Ok(()) => Ok(true),
+        Err(argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(anyhow!("verifying: {}", e)),
+    }
+}
+
+impl Passphrase {
+    pub fn new(passphrase: &str, max_attempts: u32) -> Result<Self, AnyError> {
+        Ok(Passphrase {
+            hash: hash_secret(passphrase)?,
+            max_attempts,
+            failed_attempts: 0,
+        })
+    }
+
+    /// Whether `guess` is the passphrase.
+    pub fn verify(&self, guess: &str) -> Result<bool, AnyError> {
+        verify_secret(&self.hash, guess)
+    }
+}
+
+/// One file of a bundle.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BundleEntry {
+    pub name: String,
+    pub content_type: String,
+    /// Where the file starts in the bundle's blob.
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "version")]
+enum VersionedRecord {
+    #[serde(rename = "1")]
+    V1(PasteRecord),
+}
+
+impl PasteRecord {
+    /// A record for a paste created at `now` with every view remaining. The revocation hash and
+    /// id are filled in when it is stored.
+    pub fn new(
+        storage: Storage,
+        content_type: String,
+        size: u64,
+        (expiration, max_views): (u64, u64),
+        owner: Option<String>,
+        now: u64,
+    ) -> Self {
+        PasteRecord {
+            storage,
+            content_type,
+            expiration,
+            created_at: now,
+            size,
+            options: PasteOptions { max_views },
+            remaining_views: max_views,
+            revocation_hash: None,
+            downloading_since: None,
+            download_token_hash: None,
+            owner,
+            id: None,
+            label: None,
+            not_before: None,
+            bundle: None,
+            passphrase: None,
+            inbox: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expiration
+    }
+
+    /// Whether the paste is still under embargo at `now`.
+    pub fn is_embargoed(&self, now: u64) -> bool {
+        self.not_before.is_some_and(|not_before| now < not_before)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(&VersionedRecord::V1(self.clone()))
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        match serde_json::from_slice(bytes)? {
+            VersionedRecord::V1(record) => Ok(record),
+        }
+    }
+}
+
+/// Length of the header the frontend writes ahead of every body: the SHA-256 of the password the
+/// body is encrypted with, or zeroes when there is none.
+pub const PASSWORD_HEADER_LEN: usize = 32;
+
+/// Whether a body starting with `header` was encrypted with a password.
+pub fn password_protected(header: &[u8]) -> bool {
+    header.len() == PASSWORD_HEADER_LEN && header.iter().any(|&b| b != 0)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub enum View {
+    Missing,
+    Expired(PasteRecord),
+    Live {
+        record: PasteRecord,
+        /// The body, for [`Storage::Inline`] pastes.
+        body: Option<Bytes>,
+    },
+}
+
+pub enum Consumed {
+    /// The paste had already expired and must be removed.
+    Expired,
+    /// That was the last view; the paste must be removed.
+    Burned,
+    /// Views remain; the updated record must be written back.
+    Remaining,
+}
+
+impl PasteRecord {
+    /// Takes one view from the record. Every [`PasteStore`](crate::store::PasteStore) applies
+    /// the outcome within the same transaction that read the record.
+    pub fn consume(&mut self, now: u64) -> Consumed {
+        if self.is_expired(now) {
+            return Consumed::Expired;
+        }
+        self.remaining_views = self.remaining_views.saturating_sub(1);
+        self.downloading_since = None;
+        self.download_token_hash = None;
+        if self.remaining_views == 0 {
+            Consumed::Burned
+        } else {
+            Consumed::Remaining
+        }
+    }
+}
+
+/// Bounds on how long a paste may live, in seconds from creation.
+#[derive(Clone, Copy, Debug)]
+pub struct Lifetimes {
+    pub min: u64,
+    pub default: u64,
+    pub max: u64,
+}
+
+impl Lifetimes {
+    /// Resolves the `x-paste-expiration` and `x-paste-ttl` headers of a new paste into its
+    /// expiration, falling back to the default lifetime when neither is given.
+    pub fn expiration(
+        &self,
+        now: u64,
+        expiration: Option<u64>,
+        ttl: Option<u64>,
+    ) -> Result<u64, AnyError> {
+        let lifetime = match (expiration, ttl) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "x-paste-expiration and x-paste-ttl are mutually exclusive"
+                ))
+            }
+            (Some(expiration), None) => {
+                if expiration <= now {
+                    return Err(anyhow!("x-paste-expiration is in the past"));
+                }
+                expiration - now
+            }
+            (None, Some(ttl)) => ttl,
+            (None, None) => self.default,
+        };
+        if lifetime < self.min {
+            return Err(anyhow!("pastes must live at least {} seconds", self.min));
+        }
+        if lifetime > self.max {
+            return Err(anyhow!("pastes may live at most {} seconds", self.max));
+        }
+        Ok(now + lifetime)
+    }
+}
@@ -0,0 +1,255 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use sled::transaction::ConflictableTransactionError;
+use sled::Transactional;
+use tokio::sync::broadcast;
+
+use crate::paste::PasteRecord;
+use crate::webhook::{Kind, Webhooks};
+use crate::Error;
+
+/// How long a receipt is kept after its paste would have expired, in seconds.
+const RECEIPT_LIFETIME: u64 = 30 * 24 * 60 * 60;
+
+/// Events buffered for each subscriber before the slowest start missing some.
+const EVENT_BACKLOG: usize = 64;
+
+/// How a paste came to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    /// Its last view was taken.
+    Burned,
+    /// It expired, read or not.
+    Expired,
+    /// Someone holding its revocation token took it down.
+    Revoked,
+    /// It was deleted.
+    Deleted,
+    /// It burned after too many wrong passphrases.
+    LockedOut,
+}
+
+/// What has become of a paste, for the user who created it.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Receipt {
+    pub owner: String,
+    pub created_at: u64,
+    pub expiration: u64,
+    pub max_views: u64,
+    /// When each view was taken.
+    pub views: Vec<u64>,
+    pub outcome: Option<Outcome>,
+    pub ended_at: Option<u64>,
+}
+
+impl Receipt {
+    /// A receipt for a paste nothing has happened to yet, or `None` if nobody owns it.
+    pub fn new(record: &PasteRecord) -> Option<Self> {
+        Some(Receipt {
+            owner: record.owner.clone()?,
+            created_at: record.created_at,
+            expiration: record.expiration,
+            max_views: record.options.max_views,
+            views: Vec::new(),
+            outcome: None,
+            ended_at: None,
+        })
+    }
+
+    /// The name of the event that last changed the receipt.
+    pub fn event(&self) -> &'static str {
+        match self.outcome {
+            None => "viewed",
+            Some(Outcome::Burned) => "burned",
+            Some(Outcome::Expired) => "expired",
+            Some(Outcome::Revoked) => "revoked",
+            Some(Outcome::Deleted) => "deleted",
+            Some(Outcome::LockedOut) => "locked-out",
+        }
+    }
+}
+
+/// A change to a receipt, as sent to subscribers.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Event {
+    pub key: String,
+    #[serde(flatten)]
+    pub receipt: Receipt,
+}
+
+/// Receipts in sled: `receipts` maps each paste key to its [`Receipt`], and `receipt-expirations`
+/// indexes keys by the big-endian time at which the receipt is dropped, followed by the key. A
+/// receipt is only written once something happens to its paste; until then the paste's own
+/// record says all there is to say. Receipts are left for sled to flush in the background, so a
+/// crash can lose the last few. Every change is also passed on to the webhooks, whether or not
+/// the paste has an owner.
+#[derive(Clone)]
+pub struct Receipts {
+    receipts: sled::Tree,
+    expirations: sled::Tree,
+    events: broadcast::Sender<Event>,
+    webhooks: Webhooks,
+    logger: Arc<slog::Logger>,
+}
+
+fn expiration_key(expires: u64, key: &[u8]) -> Vec<u8> {
+    let mut index_key = expires.to_be_bytes().to_vec();
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+impl Receipts {
+    pub fn open(
+        db: &sled::Db,
+        webhooks: Webhooks,
+        logger: Arc<slog::Logger>,
+    ) -> Result<Self, sled::Error> {
+        Ok(Receipts {
+            receipts: db.open_tree("receipts")?,
+            expirations: db.open_tree("receipt-expirations")?,
+            events: broadcast::channel(EVENT_BACKLOG).0,
+            webhooks,
+            logger,
+        })
+    }
+
+    /// Every change to a receipt from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Receipt>, Error> {
+        Ok(self
+            .receipts
+            .get(key)?
+            .map(|receipt| serde_json::from_slice(&receipt))
+            .transpose()?)
+    }
+
+    /// Every receipt belonging to `owner`, skipping any that fail to decode.
+    pub fn owned(&self, owner: &str) -> Result<Vec<(String, Receipt)>, Error> {
+        let mut owned = Vec::new();
+        for entry in self.receipts.iter() {
+            let (key, receipt) = entry?;
+            match serde_json::from_slice::<Receipt>(&receipt) {
+                Ok(receipt) if receipt.owner == owner => {
+                    owned.push((String::from_utf8_lossy(&key).into_owned(), receipt))
+                }
+                _ => (),
+            }
+        }
+        Ok(owned)
+    }
+
+    /// Announces a new paste. Nothing is recorded until something happens to it.
+    pub fn created(&self, record: &PasteRecord, now: u64) {
+        self.webhooks.paste(Kind::Created, record, now);
+    }
+
+    /// Records a view of a paste, given its record after the view was taken.
+    pub fn viewed(&self, key: &str, record: &PasteRecord, now: u64) {
+        let kind = if record.remaining_views == 0 {
+            Kind::Burned
+        } else {
+            Kind::Viewed
+        };
+        self.webhooks.paste(kind, record, now);
+        self.record(key, record, |receipt| {
+            receipt.views.push(now);
+            if record.remaining_views == 0 {
+                receipt.outcome = Some(Outcome::Burned);
+                receipt.ended_at = Some(now);
+            }
+        })
+    }
+
+    /// Records the end of a paste.
+    pub fn ended(&self, key: &str, record: &PasteRecord, outcome: Outcome, now: u64) {
+        match outcome {
+            Outcome::Expired if record.remaining_views == record.options.max_views => {
+                self.webhooks.paste(Kind::ExpiredUnread, record, now)
+            }
+            Outcome::Revoked => self.webhooks.paste(Kind::Revoked, record, now),
+            Outcome::LockedOut => self.webhooks.paste(Kind::LockedOut, record, now),
+            _ => (),
+        }
+        self.record(key, record, |receipt| {
+            receipt.outcome = Some(outcome);
+            receipt.ended_at = Some(now);
+        })
+    }
+
+    /// Applies `f` to the receipt of an owned paste that has not ended yet, and tells subscribers.
+    /// Failures are only logged, since the paste itself has already changed.
+    fn record<F: Fn(&mut Receipt)>(&self, key: &str, record: &PasteRecord, f: F) {
+        let res = (&self.receipts, &self.expirations).transaction(|(receipts, expirations)| {
+            let mut receipt: Receipt = match receipts.get(key)? {
+                Some(receipt) => {
+                    serde_json::from_slice(&receipt).map_err(ConflictableTransactionError::Abort)?
+                }
+                None => match Receipt::new(record) {
+                    Some(receipt) => {
+                        expirations.insert(
+                            expiration_key(receipt.expiration + RECEIPT_LIFETIME, key.as_bytes()),
+                            &[],
+                        )?;
+                        receipt
+                    }
+                    None => return Ok(None),
+                },
+            };
+            if receipt.outcome.is_some() {
+                return Ok(None);
+            }
+            f(&mut receipt);
+            receipts.insert(
+                key.as_bytes(),
+                serde_json::to_vec(&receipt).map_err(ConflictableTransactionError::Abort)?,
+            )?;
+            Ok(Some(receipt))
+        });
+        match res {
+            Ok(Some(receipt)) => {
+                // nobody listening is fine
+                let _ = self.events.send(Event {
+                    key: key.to_owned(),
+                    receipt,
+                });
+            }
+            Ok(None) => (),
+            Err(e) => slog::error!(
+                self.logger,
+                "ERROR";
+                "context" => "record receipt",
+                "key" => key,
+                "reason" => %e,
+            ),
+        }
+    }
+
+    /// Drops every receipt due by `now`, returning how many.
+    pub fn purge(&self, now: u64) -> Result<usize, Error> {
+        let mut purged = 0;
+        for index_key in self.expirations.range(..(now + 1).to_be_bytes()).keys() {
+            let index_key = index_key?;
+            (&self.receipts, &self.expirations).transaction(|(receipts, expirations)| {
+                receipts.remove(&index_key[8..])?;
+                expirations.remove(&index_key)?;
+                Ok::<_, ConflictableTransactionError>(())
+            })?;
+            purged += 1;
+        }
+        Ok(purged)
+    }
+
+    /// The earliest time at which some receipt is dropped.
+    pub fn next_expiration(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .expirations
+            .first()?
+            .and_then(|(key, _)| key.get(..8)?.try_into().ok())
+            .map(u64::from_be_bytes))
+    }
+}
@@ -0,0 +1,114 @@
+use std::convert::TryInto;
+
+use sled::transaction::ConflictableTransactionError;
+use sled::Transactional;
+
+use crate::Error;
+
+/// Login sessions in sled: `sessions` maps each session id to its big-endian expiration, and
+/// `session-expirations` indexes ids by that expiration followed by the id.
+#[derive(Clone)]
+pub struct Sessions {
+    sessions: sled::Tree,
+    expirations: sled::Tree,
+}
+
+fn expiration_key(expiration: u64, session: &[u8]) -> Vec<u8> {
+    let mut index_key = expiration.to_be_bytes().to_vec();
+    index_key.extend_from_slice(session);
+    index_key
+}
+
+fn decode_expiration(expiration: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(expiration.get(..8)?.try_into().ok()?))
+}
+
+impl Sessions {
+    pub fn open(db: &sled::Db) -> Result<Self, sled::Error> {
+        let sessions = Sessions {
+            sessions: db.open_tree("sessions")?,
+            expirations: db.open_tree("session-expirations")?,
+        };
+        if sessions.expirations.is_empty() {
+            // sessions created before the index existed
+            for res in sessions.sessions.iter() {
+                let (session, expiration) = res?;
+                if let Some(expiration) = decode_expiration(&expiration) {
+                    sessions
+                        .expirations
+                        .insert(expiration_key(expiration, &session), &[])?;
+                }
+            }
+        }
+        Ok(sessions)
+    }
+
+    pub fn expiration(&self, session: &[u8]) -> Result<Option<u64>, Error> {
+        Ok(self
+            .sessions
+            .get(session)?
+            .and_then(|expiration| decode_expiration(&expiration)))
+    }
+
+    pub fn insert(&self, session: &[u8], expiration: u64) -> Result<(), Error> {
+        (&self.sessions, &self.expirations).transaction(|(sessions, expirations)| {
+            sessions.insert(session, &expiration.to_be_bytes())?;
+            expirations.insert(expiration_key(expiration, session), &[])?;
+            Ok::<_, ConflictableTransactionError>(())
+        })?;
+        Ok(())
+    }
+
+    pub fn remove(&self, session: &[u8]) -> Result<(), Error> {
+        (&self.sessions, &self.expirations).transaction(|(sessions, expirations)| {
+            if let Some(expiration) = sessions.remove(session)? {
+                if let Some(expiration) = decode_expiration(&expiration) {
+                    expirations.remove(expiration_key(expiration, session))?;
+                }
+            }
+            Ok::<_, ConflictableTransactionError>(())
+        })?;
+        Ok(())
+    }
+
+    /// Ends every session of `user`, whose name follows the 16 random bytes of each session id.
+    pub fn remove_user(&self, user: &str) -> Result<usize, Error> {
+        let sessions = self
+            .sessions
+            .iter()
+            .keys()
+            .filter(|session| {
+                session
+                    .as_ref()
+                    .map_or(true, |session| session.get(16..) == Some(user.as_bytes()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for session in &sessions {
+            self.remove(session)?;
+        }
+        Ok(sessions.len())
+    }
+
+    /// Ids of every session that has expired by `now`, in order of expiration.
+    pub fn expired(&self, now: u64) -> Result<Vec<Vec<u8>>, Error> {
+        self.expirations
+            .range(..now.to_be_bytes())
+            .keys()
+            .map(|key| Ok(key?[8..].to_vec()))
+            .collect()
+    }
+
+    /// The earliest time at which some session will have expired.
+    pub fn next_expiration(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .expirations
+            .first()?
+            .and_then(|(key, _)| decode_expiration(&key))
+            .map(|expiration| expiration + 1))
+    }
+
+    pub async fn flush(&self) -> Result<(), Error> {
+        futures::try_join!(self.sessions.flush_async(), self.expirations.flush_async())?;
+        Ok(())
+    }
+}
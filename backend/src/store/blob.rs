@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::body::Bytes;
+use tokio::io::AsyncWrite;
+
+use super::{erase, BlobReader, BlobStore, BlobWriter, Durability};
+use crate::Error;
+
+/// Blobs as files in `big`, staged in `tmp` and moved into place with a rename. With
+/// `secure_erase`, files are overwritten before they are unlinked.
+pub struct FsBlobs {
+    big: PathBuf,
+    tmp: PathBuf,
+    secure_erase: bool,
+    durability: Durability,
+    logger: slog::Logger,
+}
+
+impl FsBlobs {
+    pub fn new(
+        big: impl Into<PathBuf>,
+        tmp: impl Into<PathBuf>,
+        secure_erase: bool,
+        durability: Durability,
+        logger: slog::Logger,
+    ) -> Self {
+        FsBlobs {
+            big: big.into(),
+            tmp: tmp.into(),
+            secure_erase,
+            durability,
+            logger,
+        }
+    }
+
+    /// Moves a finished file into `big` under `key`, syncing it first in strict mode.
+    async fn publish(&self, path: &Path, key: &str) -> Result<u64, Error> {
+        let strict = self.durability == Durability::Strict;
+        if tokio::fs::metadata(&self.big).await.is_err() {
+            tokio::fs::create_dir_all(&self.big).await?;
+            if strict {
+                if let Some(parent) = self.big.parent() {
+                    sync_dir(parent).await?;
+                }
+            }
+        }
+        if strict {
+            tokio::fs::File::open(path).await?.sync_all().await?;
+        }
+        tokio::fs::rename(path, self.big.join(key)).await?;
+        if strict {
+            sync_dir(&self.big).await?;
+        }
+        Ok(tokio::fs::metadata(self.big.join(key)).await?.len())
+    }
+
+    /// Overwrites a file ahead of unlinking it in secure-erase mode. Failures are logged rather
+    /// than returned, so the unlink still happens.
+    async fn erase(&self, key: &str, path: &Path) {
+        if !self.secure_erase {
+            return;
+        }
+        match erase::overwrite(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => slog::error!(
+                self.logger,
+                "ERROR";
+                "context" => "secure erase",
+                "key" => key,
+                "reason" => %e,
+            ),
+            _ => (),
+        }
+    }
+}
+
+/// Makes the entries of a directory durable, such as a file just renamed into it.
+async fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+impl BlobStore for FsBlobs {
+    fn stage(&self) -> BoxFuture<'_, Result<(String, BlobWriter), Error>> {
+        async move {
+            tokio::fs::create_dir_all(&self.tmp).await?;
+            let mut tmp_file;
+            while {
+                tmp_file = format!("{}.tmp", rand::RngCore::next_u32(&mut rand::thread_rng()));
+                tokio::fs::metadata(self.tmp.join(&tmp_file)).await.is_ok()
+            } {}
+            let file = tokio::fs::File::create(self.tmp.join(&tmp_file)).await?;
+            Ok((tmp_file, Box::new(file) as BlobWriter))
+        }
+        .boxed()
+    }
+
+    fn commit<'a>(&'a self, staged: &'a str, key: &'a str) -> BoxFuture<'a, Result<u64, Error>> {
+        async move { self.publish(&self.tmp.join(staged), key).await }.boxed()
+    }
+
+    fn discard<'a>(&'a self, staged: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let path = self.tmp.join(staged);
+            self.erase(staged, &path).await;
+            match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        }
+        .boxed()
+    }
+
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<(u64, BlobReader)>, Error>> {
+        async move {
+            let file = match tokio::fs::File::open(self.big.join(key)).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let len = file.metadata().await?.len();
+            Ok(Some((len, Box::new(file) as BlobReader)))
+        }
+        .boxed()
+    }
+
+    fn import<'a>(&'a self, path: &'a Path, key: &'a str) -> BoxFuture<'a, Result<u64, Error>> {
+        self.publish(path, key).boxed()
+    }
+
+    fn keys(&self) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        async move {
+            let mut entries = match tokio::fs::read_dir(&self.big).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(e) => return Err(e.into()),
+            };
+            let mut keys = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_file() {
+                    keys.push(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+            Ok(keys)
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let path = self.big.join(key);
+            self.erase(key, &path).await;
+            Ok(tokio::fs::remove_file(path).await?)
+        }
+        .boxed()
+    }
+}
+
+/// Blobs held in memory, for running without touching disk.
+#[derive(Default)]
+pub struct MemoryBlobs {
+    blobs: Mutex<HashMap<String, Bytes>>,
+    staged: Mutex<HashMap<String, Arc<Mutex<Vec<u8>>>>>,
+}
+
+struct MemoryBlobWriter(Arc<Mutex<Vec<u8>>>);
+
+impl AsyncWrite for MemoryBlobWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<tokio::io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<tokio::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<tokio::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl BlobStore for MemoryBlobs {
+    fn stage(&self) -> BoxFuture<'_, Result<(String, BlobWriter), Error>> {
+        async move {
+            let buf = Arc::new(Mutex::new(Vec::new()));
+            let mut staged = self.staged.lock().unwrap();
+            let mut id;
+            while {
+                id = rand::RngCore::next_u32(&mut rand::thread_rng()).to_string();
+                staged.contains_key(&id)
+            } {}
+            staged.insert(id.clone(), buf.clone());
+            Ok((id, Box::new(MemoryBlobWriter(buf)) as BlobWriter))
+        }
+        .boxed()
+    }
+
+    fn commit<'a>(&'a self, staged: &'a str, key: &'a str) -> BoxFuture<'a, Result<u64, Error>> {
+        async move {
+            let buf = self
+                .staged
+                .lock()
+                .unwrap()
+                .remove(staged)
+                .ok_or_else(|| anyhow!("no staged blob {}", staged))?;
+            let blob = Bytes::from(std::mem::take(&mut *buf.lock().unwrap()));
+            let len = blob.len() as u64;
+            self.blobs.lock().unwrap().insert(key.to_owned(), blob);
+            Ok(len)
+        }
+        .boxed()
+    }
+
+    fn discard<'a>(&'a self, staged: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.staged.lock().unwrap().remove(staged);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn import<'a>(&'a self, path: &'a Path, key: &'a str) -> BoxFuture<'a, Result<u64, Error>> {
+        async move {
+            let blob = Bytes::from(tokio::fs::read(path).await?);
+            tokio::fs::remove_file(path).await?;
+            let len = blob.len() as u64;
+            self.blobs.lock().unwrap().insert(key.to_owned(), blob);
+            Ok(len)
+        }
+        .boxed()
+    }
+
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<(u64, BlobReader)>, Error>> {
+        async move {
+            Ok(self
+                .blobs
+                .lock()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .map(|blob| (blob.len() as u64, Box::new(Cursor::new(blob)) as BlobReader)))
+        }
+        .boxed()
+    }
+
+    fn keys(&self) -> BoxFuture<'_, Result<Vec<String>, Error>> {
+        async move { Ok(self.blobs.lock().unwrap().keys().cloned().collect()) }.boxed()
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.blobs
+                .lock()
+                .unwrap()
+                .remove(key)
+                .ok_or_else(|| anyhow!("no blob {}", key))?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
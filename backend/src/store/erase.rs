@@ -0,0 +1,42 @@
+use std::io::Write;
+use std::path::Path;
+
+use tokio::io::AsyncWriteExt;
+
+const CHUNK: usize = 1 << 20;
+
+/// Overwrites the contents of a file with zeros and syncs it, without unlinking it. On
+/// copy-on-write filesystems and flash with wear levelling the old blocks may survive anyway;
+/// this only guarantees that the file itself no longer holds the data.
+pub async fn overwrite(path: &Path) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+    let zeros = vec![0; CHUNK];
+    let mut remaining = file.metadata().await?.len();
+    while remaining > 0 {
+        let n = remaining.min(CHUNK as u64) as usize;
+        file.write_all(&zeros[..n]).await?;
+        remaining -= n as u64;
+    }
+    file.sync_all().await
+}
+
+/// Blocking [`overwrite`] of every file under `dir`, for use while nothing else has it open.
+pub fn overwrite_dir(dir: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            overwrite_dir(&entry.path())?;
+            continue;
+        }
+        let mut file = std::fs::OpenOptions::new().write(true).open(entry.path())?;
+        let zeros = vec![0; CHUNK];
+        let mut remaining = file.metadata()?.len();
+        while remaining > 0 {
+            let n = remaining.min(CHUNK as u64) as usize;
+            file.write_all(&zeros[..n])?;
+            remaining -= n as u64;
+        }
+        file.sync_all()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use super::PasteStore;
+use crate::paste::Storage;
+use crate::Error;
+
+/// An inconsistency between a store's trees, its indexes and its blobs.
+#[derive(Debug)]
+pub enum Problem {
+    /// A record that cannot be decoded. Repair removes it.
+    Corrupt(String),
+    /// An inline paste with no body. Repair removes the paste.
+    MissingBody(String),
+    /// A big paste whose blob is gone. Repair removes the paste.
+    MissingBlob(String),
+    /// A blob that belongs to no paste. Repair removes the blob.
+    OrphanBlob(String),
+    /// An entry in `index` that points at no paste, or at a paste that disagrees with it. Repair
+    /// removes the entry.
+    Dangling { index: &'static str, key: String },
+    /// A paste missing from `index`. Repair adds it.
+    Unindexed { index: &'static str, key: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Corrupt(key) => write!(f, "{}: record cannot be decoded", key),
+            Problem::MissingBody(key) => write!(f, "{}: inline paste has no body", key),
+            Problem::MissingBlob(key) => write!(f, "{}: blob is missing", key),
+            Problem::OrphanBlob(key) => write!(f, "{}: blob belongs to no paste", key),
+            Problem::Dangling { index, key } => {
+                write!(f, "{}: {} entry belongs to no paste", key, index)
+            }
+            Problem::Unindexed { index, key } => write!(f, "{}: missing from {}", key, index),
+        }
+    }
+}
+
+/// Checks a store against its blobs: every big paste needs a blob and every blob needs a big
+/// paste. Runs [`PasteStore::check`] first, so the store is internally consistent before it is
+/// compared. With `repair`, each problem is fixed as it is found. Nothing else may be using the
+/// store meanwhile, or an upload between committing its blob and creating its record would look
+/// like an orphan.
+pub async fn fsck(pastes: &dyn PasteStore, repair: bool) -> Result<Vec<Problem>, Error> {
+    let mut problems = pastes.check(repair)?;
+    let mut orphans: HashSet<String> = pastes.blobs().keys().await?.into_iter().collect();
+    for (key, record) in pastes.records()? {
+        if record.storage != Storage::Big || orphans.remove(&key) {
+            continue;
+        }
+        if repair {
+            pastes.take(&key)?;
+        }
+        problems.push(Problem::MissingBlob(key));
+    }
+    let mut orphans: Vec<_> = orphans.into_iter().collect();
+    orphans.sort();
+    for key in orphans {
+        if repair {
+            pastes.blobs().remove(&key).await?;
+        }
+        problems.push(Problem::OrphanBlob(key));
+    }
+    if repair && !problems.is_empty() {
+        pastes.flush().await?;
+    }
+    Ok(problems)
+}
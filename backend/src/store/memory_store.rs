@@ -0,0 +1,214 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::body::Bytes;
+
+use super::{BlobStore, MemoryBlobs, PasteStore, Problem, Usage};
+use crate::paste::{Consumed, PasteRecord, Storage, View};
+use crate::Error;
+
+#[derive(Default)]
+struct Pastes {
+    records: BTreeMap<String, PasteRecord>,
+    data: HashMap<String, Bytes>,
+    revocations: HashMap<Vec<u8>, String>,
+    expirations: BTreeSet<(u64, String)>,
+}
+
+impl Pastes {
+    fn remove(&mut self, key: &str) -> Option<PasteRecord> {
+        let record = self.records.remove(key)?;
+        self.data.remove(key);
+        if let Some(revocation_hash) = &record.revocation_hash {
+            self.revocations.remove(revocation_hash);
+        }
+        self.expirations
+            .remove(&(record.expiration, key.to_owned()));
+        Some(record)
+    }
+}
+
+/// Pastes held in memory and lost on restart. A single lock around all of the state makes every
+/// operation atomic.
+pub struct MemoryStore {
+    pastes: Mutex<Pastes>,
+    blobs: MemoryBlobs,
+    usage: Usage,
+}
+
+impl MemoryStore {
+    pub fn new(blobs: MemoryBlobs) -> Self {
+        MemoryStore {
+            pastes: Mutex::new(Pastes::default()),
+            blobs,
+            usage: Usage::default(),
+        }
+    }
+}
+
+impl PasteStore for MemoryStore {
+    fn contains_key(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.pastes.lock().unwrap().records.contains_key(key))
+    }
+
+    fn create<'a>(
+        &'a self,
+        key: &'a str,
+        record: &'a PasteRecord,
+        body: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<bool, Error>> {
+        async move {
+            let mut pastes = self.pastes.lock().unwrap();
+            if pastes.records.contains_key(key) {
+                return Ok(false);
+            }
+            pastes.records.insert(key.to_owned(), record.clone());
+            if let Some(body) = body {
+                pastes
+                    .data
+                    .insert(key.to_owned(), Bytes::copy_from_slice(body));
+            }
+            if let Some(revocation_hash) = &record.revocation_hash {
+                pastes
+                    .revocations
+                    .insert(revocation_hash.clone(), key.to_owned());
+            }
+            pastes
+                .expirations
+                .insert((record.expiration, key.to_owned()));
+            self.usage.add(record.size);
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    fn take(&self, key: &str) -> Result<Option<PasteRecord>, Error> {
+        let record = self.pastes.lock().unwrap().remove(key);
+        if let Some(record) = &record {
+            self.usage.sub(record.size);
+        }
+        Ok(record)
+    }
+
+    fn revoke(&self, token_hash: &[u8]) -> Result<Option<(String, PasteRecord)>, Error> {
+        let mut pastes = self.pastes.lock().unwrap();
+        let key = match pastes.revocations.get(token_hash) {
+            Some(key) => key.clone(),
+            None => return Ok(None),
+        };
+        let record = pastes.remove(&key);
+        if let Some(record) = &record {
+            self.usage.sub(record.size);
+        }
+        Ok(record.map(|record| (key, record)))
+    }
+
+    fn view<'a>(&'a self, key: &'a str, now: u64) -> BoxFuture<'a, Result<View, Error>> {
+        async move {
+            let mut pastes = self.pastes.lock().unwrap();
+            let mut record = match pastes.records.get(key) {
+                Some(record) => record.clone(),
+                None => return Ok(View::Missing),
+            };
+            let body = match record.storage {
+                Storage::Inline => Some(pastes.data.get(key).cloned().unwrap_or_default()),
+                Storage::Big => None,
+            };
+            match record.consume(now) {
+                Consumed::Expired => {
+                    pastes.remove(key);
+                    self.usage.sub(record.size);
+                    return Ok(View::Expired(record));
+                }
+                Consumed::Burned => {
+                    pastes.remove(key);
+                    self.usage.sub(record.size);
+                }
+                Consumed::Remaining => {
+                    pastes.records.insert(key.to_owned(), record.clone());
+                }
+            }
+            Ok(View::Live { record, body })
+        }
+        .boxed()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<PasteRecord>, Error> {
+        Ok(self.pastes.lock().unwrap().records.get(key).cloned())
+    }
+
+    fn peek(&self, key: &str, len: usize) -> Result<Option<Bytes>, Error> {
+        Ok(self
+            .pastes
+            .lock()
+            .unwrap()
+            .data
+            .get(key)
+            .map(|body| body.slice(..len.min(body.len()))))
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        f: &(dyn Fn(&mut PasteRecord) + Sync),
+    ) -> Result<Option<PasteRecord>, Error> {
+        let mut pastes = self.pastes.lock().unwrap();
+        Ok(pastes.records.get_mut(key).map(|record| {
+            f(record);
+            record.clone()
+        }))
+    }
+
+    fn expired(&self, now: u64) -> Result<Vec<String>, Error> {
+        Ok(self
+            .pastes
+            .lock()
+            .unwrap()
+            .expirations
+            .iter()
+            .take_while(|(expiration, _)| *expiration <= now)
+            .map(|(_, key)| key.clone())
+            .collect())
+    }
+
+    fn next_expiration(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .pastes
+            .lock()
+            .unwrap()
+            .expirations
+            .iter()
+            .next()
+            .map(|(expiration, _)| *expiration))
+    }
+
+    fn records(&self) -> Result<Vec<(String, PasteRecord)>, Error> {
+        Ok(self
+            .pastes
+            .lock()
+            .unwrap()
+            .records
+            .iter()
+            .map(|(key, record)| (key.clone(), record.clone()))
+            .collect())
+    }
+
+    fn check(&self, _repair: bool) -> Result<Vec<Problem>, Error> {
+        // every operation updates all of the maps under one lock, and nothing outlives a restart
+        Ok(Vec::new())
+    }
+
+    fn used(&self) -> u64 {
+        self.usage.get()
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        async { Ok(()) }.boxed()
+    }
+
+    fn blobs(&self) -> &dyn BlobStore {
+        &self.blobs
+    }
+}
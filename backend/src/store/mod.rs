@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Error as AnyError;
+use futures::future::BoxFuture;
+use hyper::body::Bytes;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+use crate::paste::{PasteRecord, View};
+use crate::Error;
+
+mod blob;
+mod erase;
+mod fsck;
+mod memory_store;
+mod sled_store;
+mod sqlite_store;
+
+pub use blob::{FsBlobs, MemoryBlobs};
+pub use erase::{overwrite, overwrite_dir};
+pub use fsck::{fsck, Problem};
+pub use memory_store::MemoryStore;
+pub use sled_store::{compact as compact_sled, SledStore};
+pub use sqlite_store::SqliteStore;
+
+pub type Store = Arc<dyn PasteStore>;
+pub type BlobReader = Box<dyn BlobRead>;
+pub type BlobWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A blob opened for reading, which can seek so ranges of it can be served.
+pub trait BlobRead: AsyncRead + AsyncSeek + Send + Unpin {}
+impl<T: AsyncRead + AsyncSeek + Send + Unpin> BlobRead for T {}
+
+/// Persistence for paste records and inline bodies. Every method that reads and then modifies a
+/// paste must do so atomically, so concurrent requests can never observe more views than a paste
+/// allows.
+pub trait PasteStore: Send + Sync {
+    fn contains_key(&self, key: &str) -> Result<bool, Error>;
+
+    /// Writes a new paste and its inline body, if any. Returns `false` without writing anything if
+    /// `key` is already taken. With [`Durability::Strict`] the paste survives a crash once this
+    /// resolves.
+    fn create<'a>(
+        &'a self,
+        key: &'a str,
+        record: &'a PasteRecord,
+        body: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<bool, Error>>;
+
+    /// Removes a paste, returning its record if it existed.
+    fn take(&self, key: &str) -> Result<Option<PasteRecord>, Error>;
+
+    /// Removes the paste whose revocation token hashes to `token_hash`, returning its key and
+    /// record.
+    fn revoke(&self, token_hash: &[u8]) -> Result<Option<(String, PasteRecord)>, Error>;
+
+    /// Consumes one view of a paste. Expired pastes, and pastes whose last view was just taken,
+    /// are removed.
+    fn view<'a>(&'a self, key: &'a str, now: u64) -> BoxFuture<'a, Result<View, Error>>;
+
+    /// Reads a paste's record without consuming a view.
+    fn get(&self, key: &str) -> Result<Option<PasteRecord>, Error>;
+
+    /// Reads up to `len` bytes from the start of an inline paste's body without consuming a view.
+    fn peek(&self, key: &str, len: usize) -> Result<Option<Bytes>, Error>;
+
+    /// Changes a paste's record in place, returning the result. `f` may be called more than once
+    /// and must not change the expiration or revocation hash, which are indexed.
+    fn update(
+        &self,
+        key: &str,
+        f: &(dyn Fn(&mut PasteRecord) + Sync),
+    ) -> Result<Option<PasteRecord>, Error>;
+
+    /// Keys of every paste that has expired by `now`, in order of expiration. Implementations
+    /// answer this from an index, without visiting unexpired pastes.
+    fn expired(&self, now: u64) -> Result<Vec<String>, Error>;
+
+    /// The earliest expiration of any stored paste.
+    fn next_expiration(&self) -> Result<Option<u64>, Error>;
+
+    /// Every stored record, skipping any that fail to decode.
+    fn records(&self) -> Result<Vec<(String, PasteRecord)>, Error>;
+
+    /// Finds inconsistencies within the store itself, such as index entries left behind by a
+    /// removed paste, and with `repair` fixes them. Blobs are checked separately by [`fsck`].
+    fn check(&self, repair: bool) -> Result<Vec<Problem>, Error>;
+
+    /// Total size of every stored paste body, in bytes.
+    fn used(&self) -> u64;
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>>;
+
+    /// Where the bodies of [`Storage::Big`](crate::paste::Storage::Big) pastes live.
+    fn blobs(&self) -> &dyn BlobStore;
+}
+
+/// Persistence for large paste bodies, which are streamed in and out rather than held in memory.
+pub trait BlobStore: Send + Sync {
+    /// Starts a new blob that is invisible to readers until it is committed. Returns an id for
+    /// [`commit`](Self::commit) or [`discard`](Self::discard) along with the writer.
+    fn stage(&self) -> BoxFuture<'_, Result<(String, BlobWriter), Error>>;
+
+    /// Publishes a staged blob under `key`, returning its length. With [`Durability::Strict`] the
+    /// blob survives a crash once this returns.
+    fn commit<'a>(&'a self, staged: &'a str, key: &'a str) -> BoxFuture<'a, Result<u64, Error>>;
+
+    fn discard<'a>(&'a self, staged: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Publishes the file at `path` under `key`, moving it rather than copying where possible,
+    /// and returns its length. Durable like [`commit`](Self::commit).
+    fn import<'a>(&'a self, path: &'a Path, key: &'a str) -> BoxFuture<'a, Result<u64, Error>>;
+
+    /// Opens a blob and returns its length, or `None` if there is no blob under `key`.
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<(u64, BlobReader)>, Error>>;
+
+    /// Keys of every committed blob.
+    fn keys(&self) -> BoxFuture<'_, Result<Vec<String>, Error>>;
+
+    /// Removes a blob. Readers opened earlier must be done with it first: in secure-erase mode
+    /// the contents are overwritten before the blob is unlinked.
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// Running total of the bytes held by a store, adjusted as pastes come and go rather than
+/// recomputed on every upload.
+#[derive(Default)]
+struct Usage(AtomicU64);
+
+impl Usage {
+    fn add(&self, size: u64) {
+        self.0.fetch_add(size, Ordering::SeqCst);
+    }
+
+    fn sub(&self, size: u64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+
+    /// Accounts for a paste removed by [`PasteStore::view`].
+    fn viewed(&self, view: &View) {
+        match view {
+            View::Expired(record) => self.sub(record.size),
+            View::Live { record, .. } if record.remaining_views == 0 => self.sub(record.size),
+            _ => (),
+        }
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+    #[default]
+    Sled,
+    Sqlite,
+    Memory,
+}
+
+/// When a new paste reaches the disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Durability {
+    /// Blobs, their directory entries and records are synced before a paste's link is returned.
+    #[default]
+    Strict,
+    /// Writes are left for the OS and database to flush in the background, so a power loss can
+    /// lose pastes created in the last moments before it.
+    Relaxed,
+}
+
+/// Opens the configured paste store. The sled store shares `db` with the session tree, and
+/// migrates any pastes still in the legacy per-field layout.
+pub async fn open(
+    logger: &slog::Logger,
+    backend: StorageBackend,
+    db: &sled::Db,
+    sqlite_path: PathBuf,
+    secure_erase: bool,
+    durability: Durability,
+) -> Result<Store, AnyError> {
+    let blobs = FsBlobs::new("big", "tmp", secure_erase, durability, logger.clone());
+    Ok(match backend {
+        StorageBackend::Sled => {
+            let store = SledStore::open(db, blobs, durability)?;
+            let migrated = sled_store::migrate(db, &store).await?;
+            if migrated > 0 {
+                slog::info!(logger, "migrated pastes to records"; "migrated" => migrated);
+            }
+            Arc::new(store)
+        }
+        StorageBackend::Sqlite => Arc::new(SqliteStore::open(
+            sqlite_path,
+            blobs,
+            secure_erase,
+            durability,
+        )?),
+        StorageBackend::Memory => Arc::new(MemoryStore::new(MemoryBlobs::default())),
+    })
+}
@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Error as AnyError};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::body::Bytes;
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionalTree,
+};
+use sled::Transactional;
+
+use super::{BlobStore, Durability, FsBlobs, PasteStore, Problem, Usage};
+use crate::paste::{self, Consumed, PasteOptions, PasteRecord, Storage, View};
+use crate::Error;
+
+type Trees = (
+    TransactionalTree,
+    TransactionalTree,
+    TransactionalTree,
+    TransactionalTree,
+);
+
+/// Pastes in sled: `pastes` maps each key to its encoded [`PasteRecord`], `data` holds the bodies
+/// of inline pastes, `revocations` maps the hash of a revocation token back to its paste key, and
+/// `expirations` indexes keys by their big-endian expiration timestamp followed by the key. sled
+/// only writes to disk in the background, so [`Durability::Strict`] flushes after each creation.
+pub struct SledStore {
+    records: sled::Tree,
+    data: sled::Tree,
+    revocations: sled::Tree,
+    expirations: sled::Tree,
+    blobs: FsBlobs,
+    durability: Durability,
+    usage: Usage,
+}
+
+fn abort(e: serde_json::Error) -> ConflictableTransactionError<serde_json::Error> {
+    ConflictableTransactionError::Abort(e)
+}
+
+fn expiration_key(expiration: u64, key: &[u8]) -> Vec<u8> {
+    let mut index_key = expiration.to_be_bytes().to_vec();
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+/// Removes a paste as part of an enclosing transaction, returning its record.
+fn remove(
+    (records, data, revocations, expirations): &Trees,
+    key: &[u8],
+) -> ConflictableTransactionResult<Option<PasteRecord>, serde_json::Error> {
+    let record = match records.remove(key)? {
+        Some(record) => PasteRecord::decode(&record).map_err(abort)?,
+        None => return Ok(None),
+    };
+    if record.storage == Storage::Inline {
+        data.remove(key)?;
+    }
+    if let Some(revocation_hash) = &record.revocation_hash {
+        revocations.remove(revocation_hash.as_slice())?;
+    }
+    expirations.remove(expiration_key(record.expiration, key))?;
+    Ok(Some(record))
+}
+
+fn insert(
+    (records, data, revocations, expirations): &Trees,
+    key: &[u8],
+    record: &PasteRecord,
+    body: Option<&[u8]>,
+) -> ConflictableTransactionResult<(), serde_json::Error> {
+    records.insert(key, record.encode().map_err(abort)?)?;
+    if let Some(body) = body {
+        data.insert(key, body)?;
+    }
+    if let Some(revocation_hash) = &record.revocation_hash {
+        revocations.insert(revocation_hash.as_slice(), key)?;
+    }
+    expirations.insert(expiration_key(record.expiration, key), &[])?;
+    Ok(())
+}
+
+impl SledStore {
+    pub fn open(
+        db: &sled::Db,
+        blobs: FsBlobs,
+        durability: Durability,
+    ) -> Result<Self, sled::Error> {
+        let store = SledStore {
+            records: db.open_tree("pastes")?,
+            data: db.open_tree("data")?,
+            revocations: db.open_tree("revocations")?,
+            expirations: db.open_tree("expirations")?,
+            blobs,
+            durability,
+            usage: Usage::default(),
+        };
+        // records written before the expiration index existed need adding to it
+        let reindex = store.expirations.is_empty();
+        for res in store.records.iter() {
+            let (key, record) = res?;
+            if let Ok(record) = PasteRecord::decode(&record) {
+                store.usage.add(record.size);
+                if reindex {
+                    store
+                        .expirations
+                        .insert(expiration_key(record.expiration, &key), &[])?;
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    fn trees(&self) -> (&sled::Tree, &sled::Tree, &sled::Tree, &sled::Tree) {
+        (
+            &self.records,
+            &self.data,
+            &self.revocations,
+            &self.expirations,
+        )
+    }
+}
+
+impl PasteStore for SledStore {
+    fn contains_key(&self, key: &str) -> Result<bool, Error> {
+        Ok(self.records.contains_key(key)?)
+    }
+
+    fn create<'a>(
+        &'a self,
+        key: &'a str,
+        record: &'a PasteRecord,
+        body: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<bool, Error>> {
+        async move {
+            let created = self.trees().transaction(|trees| {
+                if trees.0.get(key.as_bytes())?.is_some() {
+                    return Ok(false);
+                }
+                insert(trees, key.as_bytes(), record, body)?;
+                Ok(true)
+            })?;
+            if created {
+                self.usage.add(record.size);
+                if self.durability == Durability::Strict {
+                    // flushes every tree sharing the database, not just this one
+                    self.records.flush_async().await?;
+                }
+            }
+            Ok(created)
+        }
+        .boxed()
+    }
+
+    fn take(&self, key: &str) -> Result<Option<PasteRecord>, Error> {
+        let record = self
+            .trees()
+            .transaction(|trees| remove(trees, key.as_bytes()))?;
+        if let Some(record) = &record {
+            self.usage.sub(record.size);
+        }
+        Ok(record)
+    }
+
+    fn revoke(&self, token_hash: &[u8]) -> Result<Option<(String, PasteRecord)>, Error> {
+        let revoked = self.trees().transaction(|trees| {
+            let key = match trees.2.get(token_hash)? {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            Ok(remove(trees, &key)?
+                .map(|record| (String::from_utf8_lossy(&key).into_owned(), record)))
+        })?;
+        if let Some((_, record)) = &revoked {
+            self.usage.sub(record.size);
+        }
+        Ok(revoked)
+    }
+
+    fn view<'a>(&'a self, key: &'a str, now: u64) -> BoxFuture<'a, Result<View, Error>> {
+        async move {
+            let view = self.trees().transaction(|trees| {
+                let mut record = match trees.0.get(key.as_bytes())? {
+                    Some(record) => PasteRecord::decode(&record).map_err(abort)?,
+                    None => return Ok(View::Missing),
+                };
+                let body = match record.storage {
+                    Storage::Inline => Some(Bytes::copy_from_slice(
+                        &trees.1.get(key.as_bytes())?.unwrap_or_default(),
+                    )),
+                    Storage::Big => None,
+                };
+                match record.consume(now) {
+                    Consumed::Expired => {
+                        remove(trees, key.as_bytes())?;
+                        return Ok(View::Expired(record));
+                    }
+                    Consumed::Burned => {
+                        remove(trees, key.as_bytes())?;
+                    }
+                    Consumed::Remaining => {
+                        trees
+                            .0
+                            .insert(key.as_bytes(), record.encode().map_err(abort)?)?;
+                    }
+                }
+                Ok(View::Live { record, body })
+            })?;
+            self.usage.viewed(&view);
+            Ok(view)
+        }
+        .boxed()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<PasteRecord>, Error> {
+        Ok(self
+            .records
+            .get(key)?
+            .map(|record| PasteRecord::decode(&record))
+            .transpose()?)
+    }
+
+    fn peek(&self, key: &str, len: usize) -> Result<Option<Bytes>, Error> {
+        Ok(self
+            .data
+            .get(key)?
+            .map(|body| Bytes::copy_from_slice(&body[..len.min(body.len())])))
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        f: &(dyn Fn(&mut PasteRecord) + Sync),
+    ) -> Result<Option<PasteRecord>, Error> {
+        Ok(self.records.transaction(|records| {
+            let mut record = match records.get(key.as_bytes())? {
+                Some(record) => PasteRecord::decode(&record).map_err(abort)?,
+                None => return Ok(None),
+            };
+            f(&mut record);
+            records.insert(key.as_bytes(), record.encode().map_err(abort)?)?;
+            Ok(Some(record))
+        })?)
+    }
+
+    fn expired(&self, now: u64) -> Result<Vec<String>, Error> {
+        self.expirations
+            .range(..(now + 1).to_be_bytes())
+            .keys()
+            .map(|key| Ok(String::from_utf8_lossy(&key?[8..]).into_owned()))
+            .collect()
+    }
+
+    fn next_expiration(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .expirations
+            .first()?
+            .and_then(|(key, _)| key.get(..8)?.try_into().ok())
+            .map(u64::from_be_bytes))
+    }
+
+    fn records(&self) -> Result<Vec<(String, PasteRecord)>, Error> {
+        let mut records = Vec::new();
+        for res in self.records.iter() {
+            let (key, record) = res?;
+            if let Ok(record) = PasteRecord::decode(&record) {
+                records.push((String::from_utf8_lossy(&key).into_owned(), record));
+            }
+        }
+        Ok(records)
+    }
+
+    fn check(&self, repair: bool) -> Result<Vec<Problem>, Error> {
+        let mut problems = Vec::new();
+        let mut live = HashMap::new();
+        for res in self.records.iter() {
+            let (key, record) = res?;
+            let name = String::from_utf8_lossy(&key).into_owned();
+            let record = match PasteRecord::decode(&record) {
+                Ok(record) => record,
+                Err(_) => {
+                    // its other entries are removed below as dangling
+                    if repair {
+                        self.records.remove(&key)?;
+                    }
+                    problems.push(Problem::Corrupt(name));
+                    continue;
+                }
+            };
+            if record.storage == Storage::Inline && !self.data.contains_key(&key)? {
+                problems.push(Problem::MissingBody(name.clone()));
+                if repair {
+                    self.take(&name)?;
+                    continue;
+                }
+            }
+            let index_key = expiration_key(record.expiration, &key);
+            if !self.expirations.contains_key(&index_key)? {
+                if repair {
+                    self.expirations.insert(index_key, &[])?;
+                }
+                problems.push(Problem::Unindexed {
+                    index: "expirations",
+                    key: name.clone(),
+                });
+            }
+            if let Some(revocation_hash) = &record.revocation_hash {
+                if self.revocations.get(revocation_hash)?.as_deref() != Some(&*key) {
+                    if repair {
+                        self.revocations.insert(revocation_hash.as_slice(), &key)?;
+                    }
+                    problems.push(Problem::Unindexed {
+                        index: "revocations",
+                        key: name,
+                    });
+                }
+            }
+            live.insert(key.to_vec(), record);
+        }
+        for key in self.data.iter().keys() {
+            let key = key?;
+            if live
+                .get(&*key)
+                .is_none_or(|record| record.storage != Storage::Inline)
+            {
+                if repair {
+                    self.data.remove(&key)?;
+                }
+                problems.push(Problem::Dangling {
+                    index: "data",
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                });
+            }
+        }
+        for res in self.revocations.iter() {
+            let (revocation_hash, key) = res?;
+            if live
+                .get(&*key)
+                .is_none_or(|record| record.revocation_hash.as_deref() != Some(&*revocation_hash))
+            {
+                if repair {
+                    self.revocations.remove(&revocation_hash)?;
+                }
+                problems.push(Problem::Dangling {
+                    index: "revocations",
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                });
+            }
+        }
+        for index_key in self.expirations.iter().keys() {
+            let index_key = index_key?;
+            let key = index_key.get(8..).unwrap_or_default();
+            let expiration = index_key
+                .get(..8)
+                .and_then(|e| e.try_into().ok())
+                .map(u64::from_be_bytes);
+            if live
+                .get(key)
+                .is_none_or(|record| Some(record.expiration) != expiration)
+            {
+                if repair {
+                    self.expirations.remove(&index_key)?;
+                }
+                problems.push(Problem::Dangling {
+                    index: "expirations",
+                    key: String::from_utf8_lossy(key).into_owned(),
+                });
+            }
+        }
+        Ok(problems)
+    }
+
+    fn used(&self) -> u64 {
+        self.usage.get()
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        async move {
+            futures::try_join!(
+                self.records.flush_async(),
+                self.data.flush_async(),
+                self.revocations.flush_async(),
+                self.expirations.flush_async(),
+            )?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn blobs(&self) -> &dyn BlobStore {
+        &self.blobs
+    }
+}
+
+const LEGACY_TREES: [&str; 4] = ["content-type", "expiration", "views", "revocation"];
+
+/// Moves pastes stored in the per-field tree layout (`data`, `content-type`, `expiration`,
+/// `views`, `revocation`) into [`PasteRecord`]s, then drops the legacy trees. Each paste is
+/// converted in its own transaction, so an interrupted migration resumes cleanly on the next
+/// start. Pastes without an expiration could never be read, so their bodies are deleted instead.
+/// Returns the number of pastes migrated.
+pub async fn migrate(db: &sled::Db, store: &SledStore) -> Result<usize, AnyError> {
+    let legacy = db.tree_names();
+    if !LEGACY_TREES
+        .iter()
+        .any(|name| legacy.iter().any(|t| t == name.as_bytes()))
+    {
+        return Ok(0);
+    }
+    let content_type = db.open_tree("content-type")?;
+    let expiration = db.open_tree("expiration")?;
+    let views = db.open_tree("views")?;
+    let revocation = db.open_tree("revocation")?;
+    let now = paste::now();
+    let mut migrated = 0;
+    for key in content_type.iter().keys() {
+        let key = key?;
+        if store.records.contains_key(&key)? {
+            continue;
+        }
+        let storage = match store.data.get(&key)? {
+            Some(data) if data.is_empty() => Storage::Big,
+            Some(_) => Storage::Inline,
+            None => continue,
+        };
+        let name = String::from_utf8_lossy(&key).into_owned();
+        let blob = tokio::fs::metadata(Path::new("big").join(&name)).await.ok();
+        let expiration = match expiration
+            .get(&key)?
+            .and_then(|e| e.as_ref().try_into().ok())
+            .map(u64::from_be_bytes)
+        {
+            Some(expiration) => expiration,
+            None => {
+                // the legacy layout refused to serve these and never expired them
+                store.data.remove(&key)?;
+                if storage == Storage::Big && blob.is_some() {
+                    store
+                        .blobs
+                        .remove(&name)
+                        .await
+                        .map_err(|e| anyhow!("{}", e))?;
+                }
+                continue;
+            }
+        };
+        let size = match storage {
+            Storage::Inline => store.data.get(&key)?.map_or(0, |d| d.len() as u64),
+            Storage::Big => blob.map_or(0, |m| m.len()),
+        };
+        // the legacy layout only kept the views left, so the original limit is an estimate
+        let max_views = views
+            .get(&key)?
+            .and_then(|v| v.as_ref().try_into().ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or(1)
+            .max(1);
+        let record = PasteRecord {
+            storage,
+            content_type: String::from_utf8_lossy(&content_type.get(&key)?.unwrap_or_default())
+                .into_owned(),
+            expiration,
+            created_at: now,
+            size,
+            options: PasteOptions { max_views },
+            remaining_views: max_views,
+            revocation_hash: revocation.get(&key)?.map(|h| h.to_vec()),
+            downloading_since: None,
+            download_token_hash: None,
+            owner: None,
+            id: None,
+            label: None,
+            not_before: None,
+            bundle: None,
+            passphrase: None,
+            inbox: None,
+        };
+        store.trees().transaction(|trees| {
+            insert(trees, &key, &record, None)?;
+            if record.storage == Storage::Big {
+                trees.1.remove(&key)?;
+            }
+            Ok(())
+        })?;
+        migrated += 1;
+    }
+    for name in LEGACY_TREES {
+        db.drop_tree(name)?;
+    }
+    futures::try_join!(
+        store.records.flush_async(),
+        store.data.flush_async(),
+        store.revocations.flush_async(),
+        store.expirations.flush_async(),
+    )?;
+    Ok(migrated)
+}
+
+/// Rewrites the sled database at `path` into a fresh directory holding only live data, then
+/// overwrites and deletes the old one. sled keeps superseded values in its log segments until it
+/// reuses them, so this is how the records of burned pastes, and the bodies of inline ones kept
+/// from before secure-erase mode, are scrubbed from disk. Must run before the database is opened
+/// anywhere else, so it only runs at startup; in secure-erase mode new pastes keep their bodies
+/// out of sled altogether.
+///
+/// The old database is only set aside once the fresh copy is complete, and only erased once the
+/// fresh copy is in its place, so there is always one whole database on disk. A compaction
+/// interrupted by a crash is finished on the next start.
+pub fn compact(logger: &slog::Logger, path: &Path) -> Result<(), AnyError> {
+    let sibling = |suffix: &str| {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(suffix);
+        PathBuf::from(sibling)
+    };
+    let fresh = sibling(".compact");
+    let old = sibling(".old");
+    if old.exists() {
+        if !path.exists() {
+            // set aside, but the fresh copy never took its place
+            if fresh.exists() {
+                std::fs::rename(&fresh, path)?;
+            } else {
+                std::fs::rename(&old, path)?;
+            }
+        }
+        if old.exists() {
+            erase_old(logger, &old)?;
+        }
+    }
+    if !path.exists() {
+        return Ok(());
+    }
+    // a copy left unfinished by a crash, while the database it was copied from is still whole
+    if fresh.exists() {
+        std::fs::remove_dir_all(&fresh)?;
+    }
+    {
+        let db = sled::open(path)?;
+        let new = sled::Config::new().path(&fresh).create_new(true).open()?;
+        new.import(db.export());
+        new.flush()?;
+    }
+    std::fs::rename(path, &old)?;
+    std::fs::rename(&fresh, path)?;
+    erase_old(logger, &old)
+}
+
+fn erase_old(logger: &slog::Logger, old: &Path) -> Result<(), AnyError> {
+    if let Err(e) = super::erase::overwrite_dir(old) {
+        slog::error!(
+            logger,
+            "ERROR";
+            "context" => "secure erase",
+            "path" => %old.display(),
+            "reason" => %e,
+        );
+    }
+    std::fs::remove_dir_all(old)?;
+    Ok(())
+}
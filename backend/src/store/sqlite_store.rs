@@ -0,0 +1,452 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::body::Bytes;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+
+use super::{BlobStore, Durability, FsBlobs, PasteStore, Problem, Usage};
+use crate::paste::{Consumed, PasteRecord, Storage, View};
+use crate::Error;
+
+/// Pastes in a single SQLite table: the encoded [`PasteRecord`], the inline body if any, the
+/// revocation token hash, which is indexed for [`PasteStore::revoke`], and the expiration, which
+/// is indexed for [`PasteStore::expired`]. With `secure_erase`,
+/// SQLite zeroes deleted content and the WAL is checkpointed and truncated on every flush. With
+/// [`Durability::Strict`] every commit is synced; otherwise only checkpoints are. Creating and
+/// viewing pastes, the calls that serve uploads and downloads, run on blocking threads.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    blobs: FsBlobs,
+    secure_erase: bool,
+    usage: Usage,
+}
+
+fn transaction<T, F: FnOnce(&Transaction) -> Result<T, Error>>(
+    conn: &Mutex<Connection>,
+    f: F,
+) -> Result<T, Error> {
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let res = f(&tx)?;
+    tx.commit()?;
+    Ok(res)
+}
+
+fn remove(tx: &Transaction, key: &str) -> Result<Option<PasteRecord>, Error> {
+    let record: Option<Vec<u8>> = tx
+        .query_row(
+            "DELETE FROM pastes WHERE key = ?1 RETURNING record",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(record
+        .map(|record| PasteRecord::decode(&record))
+        .transpose()?)
+}
+
+impl SqliteStore {
+    pub fn open(
+        path: impl AsRef<Path>,
+        blobs: FsBlobs,
+        secure_erase: bool,
+        durability: Durability,
+    ) -> Result<Self, rusqlite::Error> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "secure_delete", secure_erase)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+        conn.pragma_update(
+            None,
+            "synchronous",
+            match durability {
+                Durability::Strict => "FULL",
+                Durability::Relaxed => "NORMAL",
+            },
+        )?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS pastes (
+                key TEXT PRIMARY KEY NOT NULL,
+                record BLOB NOT NULL,
+                body BLOB,
+                revocation_hash BLOB UNIQUE
+            );",
+        )?;
+        let indexed: bool = conn.query_row(
+            "SELECT count(*) > 0 FROM pragma_table_info('pastes') WHERE name = 'expiration'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !indexed {
+            // tables created before the expiration index existed
+            let tx = conn.unchecked_transaction()?;
+            tx.execute_batch("ALTER TABLE pastes ADD COLUMN expiration INTEGER;")?;
+            let records = tx
+                .prepare("SELECT key, record FROM pastes")?
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (key, record) in records {
+                if let Ok(record) = PasteRecord::decode(&record) {
+                    tx.execute(
+                        "UPDATE pastes SET expiration = ?2 WHERE key = ?1",
+                        params![key, record.expiration],
+                    )?;
+                }
+            }
+            tx.commit()?;
+        }
+        conn.execute_batch("CREATE INDEX IF NOT EXISTS pastes_expiration ON pastes (expiration);")?;
+        let usage = Usage::default();
+        for record in conn
+            .prepare("SELECT record FROM pastes")?
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+        {
+            if let Ok(record) = PasteRecord::decode(&record?) {
+                usage.add(record.size);
+            }
+        }
+        Ok(SqliteStore {
+            conn: Arc::new(Mutex::new(conn)),
+            blobs,
+            secure_erase,
+            usage,
+        })
+    }
+
+    fn transaction<T, F: FnOnce(&Transaction) -> Result<T, Error>>(
+        &self,
+        f: F,
+    ) -> Result<T, Error> {
+        transaction(&self.conn, f)
+    }
+
+    /// Like [`transaction`](Self::transaction), but on a blocking thread, since a commit may wait
+    /// for the disk.
+    async fn spawn_transaction<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Transaction) -> Result<T, Error> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || transaction(&conn, f)).await?
+    }
+}
+
+impl PasteStore for SqliteStore {
+    fn contains_key(&self, key: &str) -> Result<bool, Error> {
+        Ok(self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT 1 FROM pastes WHERE key = ?1", params![key], |_| {
+                Ok(())
+            })
+            .optional()?
+            .is_some())
+    }
+
+    fn create<'a>(
+        &'a self,
+        key: &'a str,
+        record: &'a PasteRecord,
+        body: Option<&'a [u8]>,
+    ) -> BoxFuture<'a, Result<bool, Error>> {
+        async move {
+            let encoded = record.encode()?;
+            let owned_key = key.to_owned();
+            let body = body.map(<[u8]>::to_vec);
+            let revocation_hash = record.revocation_hash.clone();
+            let expiration = record.expiration;
+            let created = self
+                .spawn_transaction(move |tx| {
+                    Ok(tx.execute(
+                        "INSERT INTO pastes (key, record, body, revocation_hash, expiration)
+                        VALUES (?1, ?2, ?3, ?4, ?5)
+                        ON CONFLICT (key) DO NOTHING",
+                        params![owned_key, encoded, body, revocation_hash, expiration],
+                    )? == 1)
+                })
+                .await?;
+            if created {
+                self.usage.add(record.size);
+            }
+            Ok(created)
+        }
+        .boxed()
+    }
+
+    fn take(&self, key: &str) -> Result<Option<PasteRecord>, Error> {
+        let record = self.transaction(|tx| remove(tx, key))?;
+        if let Some(record) = &record {
+            self.usage.sub(record.size);
+        }
+        Ok(record)
+    }
+
+    fn revoke(&self, token_hash: &[u8]) -> Result<Option<(String, PasteRecord)>, Error> {
+        let revoked = self.transaction(|tx| {
+            let key: String = match tx
+                .query_row(
+                    "SELECT key FROM pastes WHERE revocation_hash = ?1",
+                    params![token_hash],
+                    |row| row.get(0),
+                )
+                .optional()?
+            {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            Ok(remove(tx, &key)?.map(|record| (key, record)))
+        })?;
+        if let Some((_, record)) = &revoked {
+            self.usage.sub(record.size);
+        }
+        Ok(revoked)
+    }
+
+    fn view<'a>(&'a self, key: &'a str, now: u64) -> BoxFuture<'a, Result<View, Error>> {
+        async move {
+            let key = key.to_owned();
+            let view = self
+                .spawn_transaction(move |tx| {
+                    let (record, body): (Vec<u8>, Option<Vec<u8>>) = match tx
+                        .query_row(
+                            "SELECT record, body FROM pastes WHERE key = ?1",
+                            params![&key],
+                            |row| Ok((row.get(0)?, row.get(1)?)),
+                        )
+                        .optional()?
+                    {
+                        Some(row) => row,
+                        None => return Ok(View::Missing),
+                    };
+                    let mut record = PasteRecord::decode(&record)?;
+                    let body = match record.storage {
+                        Storage::Inline => Some(body.map(Bytes::from).unwrap_or_default()),
+                        Storage::Big => None,
+                    };
+                    match record.consume(now) {
+                        Consumed::Expired => {
+                            remove(tx, &key)?;
+                            return Ok(View::Expired(record));
+                        }
+                        Consumed::Burned => {
+                            remove(tx, &key)?;
+                        }
+                        Consumed::Remaining => {
+                            tx.execute(
+                                "UPDATE pastes SET record = ?2 WHERE key = ?1",
+                                params![&key, record.encode()?],
+                            )?;
+                        }
+                    }
+                    Ok(View::Live { record, body })
+                })
+                .await?;
+            self.usage.viewed(&view);
+            Ok(view)
+        }
+        .boxed()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<PasteRecord>, Error> {
+        let record: Option<Vec<u8>> = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT record FROM pastes WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(record
+            .map(|record| PasteRecord::decode(&record))
+            .transpose()?)
+    }
+
+    fn peek(&self, key: &str, len: usize) -> Result<Option<Bytes>, Error> {
+        let body: Option<Option<Vec<u8>>> = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT substr(body, 1, ?2) FROM pastes WHERE key = ?1",
+                params![key, len],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(body.flatten().map(Bytes::from))
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        f: &(dyn Fn(&mut PasteRecord) + Sync),
+    ) -> Result<Option<PasteRecord>, Error> {
+        self.transaction(|tx| {
+            let record: Vec<u8> = match tx
+                .query_row(
+                    "SELECT record FROM pastes WHERE key = ?1",
+                    params![key],
+                    |row| row.get(0),
+                )
+                .optional()?
+            {
+                Some(record) => record,
+                None => return Ok(None),
+            };
+            let mut record = PasteRecord::decode(&record)?;
+            f(&mut record);
+            tx.execute(
+                "UPDATE pastes SET record = ?2 WHERE key = ?1",
+                params![key, record.encode()?],
+            )?;
+            Ok(Some(record))
+        })
+    }
+
+    fn expired(&self, now: u64) -> Result<Vec<String>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT key FROM pastes WHERE expiration <= ?1 ORDER BY expiration")?;
+        let keys = stmt
+            .query_map(params![now], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(keys)
+    }
+
+    fn next_expiration(&self) -> Result<Option<u64>, Error> {
+        Ok(self.conn.lock().unwrap().query_row(
+            "SELECT min(expiration) FROM pastes",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn records(&self) -> Result<Vec<(String, PasteRecord)>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT key, record FROM pastes")?;
+        let mut records = Vec::new();
+        for row in stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })? {
+            let (key, record) = row?;
+            if let Ok(record) = PasteRecord::decode(&record) {
+                records.push((key, record));
+            }
+        }
+        Ok(records)
+    }
+
+    fn check(&self, repair: bool) -> Result<Vec<Problem>, Error> {
+        let mut removed = 0;
+        let problems = self.transaction(|tx| {
+            let rows = tx
+                .prepare(
+                    "SELECT key, record, body IS NOT NULL, revocation_hash, expiration
+                    FROM pastes",
+                )?
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, bool>(2)?,
+                        row.get::<_, Option<Vec<u8>>>(3)?,
+                        row.get::<_, Option<u64>>(4)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut problems = Vec::new();
+            for (key, record, has_body, revocation_hash, expiration) in rows {
+                let record = match PasteRecord::decode(&record) {
+                    Ok(record) => record,
+                    Err(_) => {
+                        if repair {
+                            tx.execute("DELETE FROM pastes WHERE key = ?1", params![key])?;
+                        }
+                        problems.push(Problem::Corrupt(key));
+                        continue;
+                    }
+                };
+                match (record.storage, has_body) {
+                    (Storage::Inline, false) => {
+                        problems.push(Problem::MissingBody(key.clone()));
+                        if repair {
+                            remove(tx, &key)?;
+                            removed += record.size;
+                            continue;
+                        }
+                    }
+                    (Storage::Big, true) => {
+                        if repair {
+                            tx.execute(
+                                "UPDATE pastes SET body = NULL WHERE key = ?1",
+                                params![key],
+                            )?;
+                        }
+                        problems.push(Problem::Dangling {
+                            index: "body",
+                            key: key.clone(),
+                        });
+                    }
+                    _ => (),
+                }
+                if expiration != Some(record.expiration) {
+                    if repair {
+                        tx.execute(
+                            "UPDATE pastes SET expiration = ?2 WHERE key = ?1",
+                            params![key, record.expiration],
+                        )?;
+                    }
+                    problems.push(Problem::Unindexed {
+                        index: "expiration",
+                        key: key.clone(),
+                    });
+                }
+                if revocation_hash != record.revocation_hash {
+                    if repair {
+                        tx.execute(
+                            "UPDATE pastes SET revocation_hash = ?2 WHERE key = ?1",
+                            params![key, record.revocation_hash],
+                        )?;
+                    }
+                    problems.push(Problem::Unindexed {
+                        index: "revocation_hash",
+                        key,
+                    });
+                }
+            }
+            Ok(problems)
+        })?;
+        self.usage.sub(removed);
+        Ok(problems)
+    }
+
+    fn used(&self) -> u64 {
+        self.usage.get()
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<(), Error>> {
+        // committed transactions are as durable as `synchronous` makes them
+        async move {
+            if self.secure_erase {
+                // old page images live on in the WAL until it is checkpointed
+                self.conn.lock().unwrap().query_row(
+                    "PRAGMA wal_checkpoint(TRUNCATE)",
+                    [],
+                    |_| Ok(()),
+                )?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn blobs(&self) -> &dyn BlobStore {
+        &self.blobs
+    }
+}
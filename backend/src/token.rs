@@ -0,0 +1,93 @@
+use crate::Error;
+
+/// What an API token may do beyond what its user may.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Scope {
+    /// Only creating pastes, and not deleting them.
+    pub create_only: bool,
+    /// Largest paste accepted, in bytes.
+    pub max_size: Option<u64>,
+    /// Longest a paste may be set to live, in seconds.
+    pub max_lifetime: Option<u64>,
+}
+
+/// A long-lived bearer token through which scripts act for the user who made it, within its
+/// [`Scope`].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    /// The user the token acts for. It stops working while they are disabled.
+    pub user: String,
+    /// Says what the token is for, in the listing.
+    pub name: Option<String>,
+    pub created_at: u64,
+    /// Unix timestamp after which the token no longer works. Without one it works until revoked.
+    pub expires: Option<u64>,
+    pub scope: Scope,
+}
+
+impl ApiToken {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| now >= expires)
+    }
+}
+
+/// API tokens in sled: `api-tokens` maps the SHA-256 of each token to its [`ApiToken`]. The token
+/// itself is only shown once, to the user who made it. Expired tokens are dropped when next
+/// looked at.
+#[derive(Clone)]
+pub struct ApiTokens {
+    tokens: sled::Tree,
+}
+
+impl ApiTokens {
+    pub fn open(db: &sled::Db) -> Result<Self, sled::Error> {
+        Ok(ApiTokens {
+            tokens: db.open_tree("api-tokens")?,
+        })
+    }
+
+    pub fn insert(&self, token_hash: &[u8], token: &ApiToken) -> Result<(), Error> {
+        self.tokens.insert(token_hash, serde_json::to_vec(token)?)?;
+        Ok(())
+    }
+
+    /// The live token whose hash is `token_hash`.
+    pub fn get(&self, token_hash: &[u8], now: u64) -> Result<Option<ApiToken>, Error> {
+        let token: ApiToken = match self.tokens.get(token_hash)? {
+            Some(token) => serde_json::from_slice(&token)?,
+            None => return Ok(None),
+        };
+        if token.is_expired(now) {
+            self.tokens.remove(token_hash)?;
+            return Ok(None);
+        }
+        Ok(Some(token))
+    }
+
+    pub fn remove(&self, token_hash: &[u8]) -> Result<(), Error> {
+        self.tokens.remove(token_hash)?;
+        Ok(())
+    }
+
+    /// Every live token of `user`, with its hash, oldest first.
+    pub fn owned_by(&self, user: &str, now: u64) -> Result<Vec<(Vec<u8>, ApiToken)>, Error> {
+        let mut tokens = Vec::new();
+        for res in self.tokens.iter() {
+            let (token_hash, token) = res?;
+            let token: ApiToken = serde_json::from_slice(&token)?;
+            if token.is_expired(now) {
+                self.tokens.remove(&token_hash)?;
+            } else if token.user == user {
+                tokens.push((token_hash.to_vec(), token));
+            }
+        }
+        tokens.sort_by_key(|(_, token)| token.created_at);
+        Ok(tokens)
+    }
+
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.tokens.flush_async().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sled::transaction::ConflictableTransactionError;
+use sled::Transactional;
+
+use crate::paste::Passphrase;
+use crate::store::{self, Durability};
+use crate::Error;
+
+/// A resumable upload in progress, and the paste it becomes once all `length` bytes arrive.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Upload {
+    pub length: u64,
+    pub content_type: String,
+    pub expiration: Option<u64>,
+    pub ttl: Option<u64>,
+    pub max_views: Option<u64>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub not_before: Option<u64>,
+    /// Hashed on creation, so the passphrase itself is never stored.
+    #[serde(default)]
+    pub passphrase: Option<Passphrase>,
+    /// `Upload-Metadata` as given at creation, echoed back to clients.
+    pub metadata: Option<String>,
+    /// Unix timestamp after which an unfinished upload is discarded.
+    pub expires: u64,
+    /// The user who created the upload, and will own the paste.
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+/// Unfinished uploads: `uploads` maps each upload id to its [`Upload`], and `upload-expirations`
+/// indexes ids by their big-endian expiry followed by the id. The bytes received so far live in
+/// `dir`, one file per upload, and the length of that file is the upload offset. Unlike `tmp`,
+/// `dir` is kept across restarts.
+#[derive(Clone)]
+pub struct Uploads {
+    uploads: sled::Tree,
+    expirations: sled::Tree,
+    dir: PathBuf,
+    secure_erase: bool,
+    durability: Durability,
+    busy: Arc<Mutex<HashSet<String>>>,
+    logger: slog::Logger,
+}
+
+/// Marks an upload as being written to until dropped.
+pub struct UploadLock {
+    busy: Arc<Mutex<HashSet<String>>>,
+    id: String,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn expiration_key(expires: u64, id: &[u8]) -> Vec<u8> {
+    let mut index_key = expires.to_be_bytes().to_vec();
+    index_key.extend_from_slice(id);
+    index_key
+}
+
+impl Uploads {
+    pub fn open(
+        db: &sled::Db,
+        dir: impl Into<PathBuf>,
+        secure_erase: bool,
+        durability: Durability,
+        logger: slog::Logger,
+    ) -> Result<Self, sled::Error> {
+        Ok(Uploads {
+            uploads: db.open_tree("uploads")?,
+            expirations: db.open_tree("upload-expirations")?,
+            dir: dir.into(),
+            secure_erase,
+            durability,
+            busy: Default::default(),
+            logger,
+        })
+    }
+
+    /// Where the bytes of an upload are kept.
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// Records a new upload with an empty file, returning its id.
+    pub async fn create(&self, upload: &Upload) -> Result<String, Error> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut bytes = [0; 24];
+        let id = loop {
+            rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut bytes);
+            let id = base64::encode_config(
+                bytes,
+                base64::Config::new(base64::CharacterSet::UrlSafe, false),
+            );
+            if !self.uploads.contains_key(&id)? {
+                break id;
+            }
+        };
+        tokio::fs::File::create(self.path(&id)).await?;
+        if self.durability == Durability::Strict {
+            tokio::fs::File::open(&self.dir).await?.sync_all().await?;
+        }
+        let encoded = serde_json::to_vec(upload)?;
+        (&self.uploads, &self.expirations).transaction(|(uploads, expirations)| {
+            uploads.insert(id.as_bytes(), encoded.as_slice())?;
+            expirations.insert(expiration_key(upload.expires, id.as_bytes()), &[])?;
+            Ok::<_, ConflictableTransactionError>(())
+        })?;
+        if self.durability == Durability::Strict {
+            self.flush().await?;
+        }
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Upload>, Error> {
+        Ok(self
+            .uploads
+            .get(id)?
+            .map(|upload| serde_json::from_slice(&upload))
+            .transpose()?)
+    }
+
+    /// How many bytes of an upload have been received.
+    pub async fn offset(&self, id: &str) -> Result<u64, Error> {
+        Ok(tokio::fs::metadata(self.path(id)).await?.len())
+    }
+
+    /// Claims an upload for writing, or returns `None` if another request already has it.
+    pub fn lock(&self, id: &str) -> Option<UploadLock> {
+        if !self.busy.lock().unwrap().insert(id.to_owned()) {
+            return None;
+        }
+        Some(UploadLock {
+            busy: self.busy.clone(),
+            id: id.to_owned(),
+        })
+    }
+
+    /// Makes the bytes appended to an upload durable, as far as the durability mode asks.
+    pub async fn sync(&self, file: &tokio::fs::File) -> Result<(), Error> {
+        if self.durability == Durability::Strict {
+            file.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    /// Forgets an upload whose file has already been moved elsewhere.
+    pub fn forget(&self, id: &str) -> Result<(), Error> {
+        (&self.uploads, &self.expirations).transaction(|(uploads, expirations)| {
+            if let Some(upload) = uploads.remove(id.as_bytes())? {
+                if let Ok(upload) = serde_json::from_slice::<Upload>(&upload) {
+                    expirations.remove(expiration_key(upload.expires, id.as_bytes()))?;
+                }
+            }
+            Ok::<_, ConflictableTransactionError>(())
+        })?;
+        // an upload that reappears after a crash has no file, and is dropped by `reconcile`
+        Ok(())
+    }
+
+    /// Forgets an upload and deletes the bytes received for it.
+    pub async fn remove(&self, id: &str) -> Result<(), Error> {
+        self.forget(id)?;
+        self.remove_file(&self.path(id)).await
+    }
+
+    async fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        if self.secure_erase {
+            match store::overwrite(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => slog::error!(
+                    self.logger,
+                    "ERROR";
+                    "context" => "secure erase",
+                    "path" => %path.display(),
+                    "reason" => %e,
+                ),
+                _ => (),
+            }
+        }
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ids of every upload that has expired by `now`, in order of expiry.
+    pub fn expired(&self, now: u64) -> Result<Vec<String>, Error> {
+        self.expirations
+            .range(..(now + 1).to_be_bytes())
+            .keys()
+            .map(|key| Ok(String::from_utf8_lossy(&key?[8..]).into_owned()))
+            .collect()
+    }
+
+    /// The earliest time at which some upload expires.
+    pub fn next_expiration(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .expirations
+            .first()?
+            .and_then(|(key, _)| key.get(..8)?.try_into().ok())
+            .map(u64::from_be_bytes))
+    }
+
+    /// Drops uploads whose file is gone, such as one that completed just before a crash, and
+    /// deletes files that belong to no upload. Returns how many of each were removed.
+    pub async fn reconcile(&self) -> Result<(usize, usize), Error> {
+        let mut missing = 0;
+        for id in self.uploads.iter().keys() {
+            let id = String::from_utf8_lossy(&id?).into_owned();
+            if tokio::fs::metadata(self.path(&id)).await.is_err() {
+                self.forget(&id)?;
+                missing += 1;
+            }
+        }
+        let mut orphans = 0;
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((missing, 0)),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if !self
+                .uploads
+                .contains_key(entry.file_name().to_string_lossy().as_bytes())?
+            {
+                self.remove_file(&entry.path()).await?;
+                orphans += 1;
+            }
+        }
+        Ok((missing, orphans))
+    }
+
+    pub async fn flush(&self) -> Result<(), Error> {
+        futures::try_join!(self.uploads.flush_async(), self.expirations.flush_async())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::Error as AnyError;
+use lazy_static::lazy_static;
+
+use crate::paste::{hash_secret, verify_secret};
+use crate::Error;
+
+/// The account whose password is the one in the service config. It is created on first start,
+/// and its password follows the config from then on.
+pub const BUILT_IN_ADMIN: &str = "admin";
+
+lazy_static! {
+    /// Checked against when a login names no account, so it takes as long as one that does.
+    static ref DUMMY_HASH: String = hash_secret("").expect("hashing an empty password");
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// May also manage accounts, see every paste, read the inbox, and invite outsiders to upload.
+    Admin,
+    /// May create pastes, and see and delete their own.
+    Uploader,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    /// Argon2id hash of the password, as a PHC string.
+    pub password_hash: String,
+    /// Disabled accounts can't log in, but keep their pastes.
+    pub disabled: bool,
+    pub created_at: u64,
+    /// The admin who created the account. The built-in admin has none.
+    pub created_by: Option<String>,
+}
+
+impl User {
+    /// A new, enabled account. Slow, since it hashes the password.
+    pub fn new(
+        name: String,
+        role: Role,
+        password: &str,
+        created_by: Option<String>,
+        now: u64,
+    ) -> Result<Self, AnyError> {
+        Ok(User {
+            name,
+            role,
+            password_hash: hash_secret(password)?,
+            disabled: false,
+            created_at: now,
+            created_by,
+        })
+    }
+}
+
+/// Accounts in sled: `users` maps each name to its [`User`].
+#[derive(Clone)]
+pub struct Users {
+    users: sled::Tree,
+}
+
+impl Users {
+    pub fn open(db: &sled::Db) -> Result<Self, sled::Error> {
+        Ok(Users {
+            users: db.open_tree("users")?,
+        })
+    }
+
+    /// Makes sure the built-in admin exists, is enabled, and has `password`, returning whether
+    /// its password changed. Slow, since it checks the password.
+    pub fn bootstrap(&self, password: &str, now: u64) -> Result<bool, Error> {
+        let mut changed = false;
+        let admin = match self.get(BUILT_IN_ADMIN)? {
+            Some(mut admin) => {
+                if !verify_secret(&admin.password_hash, password)? {
+                    admin.password_hash = hash_secret(password)?;
+                    changed = true;
+                }
+                admin.role = Role::Admin;
+                admin.disabled = false;
+                admin
+            }
+            None => User::new(BUILT_IN_ADMIN.to_owned(), Role::Admin, password, None, now)?,
+        };
+        self.put(&admin)?;
+        Ok(changed)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<User>, Error> {
+        Ok(match self.users.get(name)? {
+            Some(user) => Some(serde_json::from_slice(&user)?),
+            None => None,
+        })
+    }
+
+    /// The enabled account `name`, if `password` is its password. Slow, since it checks the
+    /// password, even when there is no such account.
+    pub fn login(&self, name: &str, password: &str) -> Result<Option<User>, Error> {
+        let user = self.get(name)?;
+        let hash = user
+            .as_ref()
+            .map_or(&*DUMMY_HASH, |user| &user.password_hash);
+        let matches = verify_secret(hash, password)?;
+        Ok(user.filter(|user| matches && !user.disabled))
+    }
+
+    /// Stores a new account, returning whether the name was free.
+    pub fn create(&self, user: &User) -> Result<bool, Error> {
+        let value = serde_json::to_vec(user)?;
+        Ok(self
+            .users
+            .compare_and_swap(&user.name, None as Option<&[u8]>, Some(value))?
+            .is_ok())
+    }
+
+    pub fn put(&self, user: &User) -> Result<(), Error> {
+        self.users.insert(&user.name, serde_json::to_vec(user)?)?;
+        Ok(())
+    }
+
+    /// Removes an account, returning it.
+    pub fn remove(&self, name: &str) -> Result<Option<User>, Error> {
+        Ok(match self.users.remove(name)? {
+            Some(user) => Some(serde_json::from_slice(&user)?),
+            None => None,
+        })
+    }
+
+    /// Every account, in order of name.
+    pub fn all(&self) -> Result<Vec<User>, Error> {
+        self.users
+            .iter()
+            .values()
+            .map(|user| Ok(serde_json::from_slice(&user?)?))
+            .collect()
+    }
+
+    /// Whether `name` is an enabled admin.
+    pub fn is_admin(&self, name: &str) -> Result<bool, Error> {
+        Ok(self
+            .get(name)?
+            .is_some_and(|user| user.role == Role::Admin && !user.disabled))
+    }
+
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.users.flush_async().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use hyper::client::HttpConnector;
+use hyper::{header, Body, Client, Request};
+use hyper_rustls::HttpsConnector;
+use sha2::Sha256;
+use sled::transaction::ConflictableTransactionError;
+use sled::Transactional;
+use tokio::sync::Notify;
+
+use crate::paste::PasteRecord;
+use crate::Error;
+
+/// How long a target has to answer before the attempt counts as failed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Seconds before the first retry of a failed delivery, doubled after every further failure.
+const FIRST_RETRY: u64 = 30;
+
+/// Longest wait between retries, in seconds.
+const MAX_RETRY: u64 = 6 * 60 * 60;
+
+/// Deliveries that have failed this many times are dropped.
+const MAX_ATTEMPTS: u32 = 12;
+
+/// Something a webhook target can be told about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    Created,
+    /// A view was taken and others remain.
+    Viewed,
+    /// The last view was taken.
+    Burned,
+    /// The paste expired without ever being viewed.
+    ExpiredUnread,
+    Revoked,
+    /// The paste burned after too many wrong passphrases.
+    LockedOut,
+    /// Free disk space dropped below the warning threshold.
+    LowDisk,
+}
+
+fn every_kind() -> Vec<Kind> {
+    vec![
+        Kind::Created,
+        Kind::Viewed,
+        Kind::Burned,
+        Kind::ExpiredUnread,
+        Kind::Revoked,
+        Kind::LockedOut,
+        Kind::LowDisk,
+    ]
+}
+
+/// Where to send events.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Target {
+    pub url: String,
+    /// Key for the HMAC-SHA256 signature sent with every payload.
+    pub secret: String,
+    #[serde(default = "every_kind")]
+    pub events: Vec<Kind>,
+}
+
+/// The JSON body of a webhook. Pastes are only ever named by their opaque id.
+#[derive(serde::Serialize)]
+struct Payload {
+    /// Unique to the delivery, so a receiver can tell a retry from a new event.
+    delivery: String,
+    event: Kind,
+    time: u64,
+    #[serde(flatten)]
+    detail: Detail,
+}
+
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum Detail {
+    Paste {
+        paste_id: String,
+        owner: Option<String>,
+        expiration: u64,
+        remaining_views: u64,
+    },
+    Disk {
+        free_space: u64,
+        threshold: u64,
+    },
+}
+
+/// A payload waiting to be sent to one target.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Delivery {
+    url: String,
+    /// Serialized once, so every attempt sends and signs the same bytes.
+    payload: String,
+    attempts: u32,
+    due: u64,
+}
+
+/// Outbound webhooks, queued in sled: `webhooks` maps each delivery id to its [`Delivery`], and
+/// `webhook-schedule` indexes ids by the big-endian time of their next attempt, followed by the
+/// id. Deliveries are left for sled to flush in the background, like receipts, and are retried
+/// with exponential backoff until the target accepts them with a 2xx. A delivery whose target has
+/// been removed from the config is dropped.
+#[derive(Clone)]
+pub struct Webhooks {
+    targets: Arc<Vec<Target>>,
+    deliveries: sled::Tree,
+    schedule: sled::Tree,
+    client: Client<HttpsConnector<HttpConnector>>,
+    wake: Arc<Notify>,
+    logger: Arc<slog::Logger>,
+}
+
+fn schedule_key(due: u64, id: &[u8]) -> Vec<u8> {
+    let mut index_key = due.to_be_bytes().to_vec();
+    index_key.extend_from_slice(id);
+    index_key
+}
+
+/// A random id for a paste or delivery.
+pub fn new_id() -> String {
+    let mut bytes = [0; 16];
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut bytes);
+    base64::encode_config(
+        bytes,
+        base64::Config::new(base64::CharacterSet::UrlSafe, false),
+    )
+}
+
+fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(payload.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Webhooks {
+    pub fn open(
+        db: &sled::Db,
+        targets: Vec<Target>,
+        logger: Arc<slog::Logger>,
+    ) -> Result<Self, sled::Error> {
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Ok(Webhooks {
+            targets: Arc::new(targets),
+            deliveries: db.open_tree("webhooks")?,
+            schedule: db.open_tree("webhook-schedule")?,
+            client: Client::builder().build(connector),
+            wake: Arc::new(Notify::new()),
+            logger,
+        })
+    }
+
+    /// Notified whenever a delivery is queued.
+    pub fn wake(&self) -> Arc<Notify> {
+        self.wake.clone()
+    }
+
+    /// Queues an event about a paste. Pastes from before ids were handed out are skipped, since
+    /// there is nothing to name them by.
+    pub fn paste(&self, kind: Kind, record: &PasteRecord, now: u64) {
+        if let Some(id) = &record.id {
+            self.queue(
+                kind,
+                now,
+                Detail::Paste {
+                    paste_id: id.clone(),
+                    owner: record.owner.clone(),
+                    expiration: record.expiration,
+                    remaining_views: record.remaining_views,
+                },
+            )
+        }
+    }
+
+    /// Queues a warning that only `free_space` bytes are left on disk.
+    pub fn low_disk(&self, free_space: u64, threshold: u64, now: u64) {
+        self.queue(
+            Kind::LowDisk,
+            now,
+            Detail::Disk {
+                free_space,
+                threshold,
+            },
+        )
+    }
+
+    /// Queues a delivery to every target that wants `kind`. Failures are only logged, since
+    /// whatever the event reports has already happened.
+    fn queue(&self, kind: Kind, now: u64, detail: Detail) {
+        let targets: Vec<&Target> = self
+            .targets
+            .iter()
+            .filter(|target| target.events.contains(&kind))
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        let id = new_id();
+        let res = serde_json::to_string(&Payload {
+            delivery: id.clone(),
+            event: kind,
+            time: now,
+            detail,
+        })
+        .map_err(Error::from)
+        .and_then(|payload| {
+            for (n, target) in targets.into_iter().enumerate() {
+                // one delivery per target, all sharing the payload's id
+                let id = format!("{}.{}", id, n);
+                self.put(
+                    &id,
+                    &Delivery {
+                        url: target.url.clone(),
+                        payload: payload.clone(),
+                        attempts: 0,
+                        due: now,
+                    },
+                    None,
+                )?;
+            }
+            Ok(())
+        });
+        match res {
+            Ok(()) => self.wake.notify_one(),
+            Err(e) => slog::error!(
+                self.logger,
+                "ERROR";
+                "context" => "queue webhook",
+                "event" => ?kind,
+                "reason" => %e,
+            ),
+        }
+    }
+
+    /// Stores a delivery under `id`, moving it in the schedule from `previous_due` if given.
+    fn put(&self, id: &str, delivery: &Delivery, previous_due: Option<u64>) -> Result<(), Error> {
+        let value = serde_json::to_vec(delivery)?;
+        (&self.deliveries, &self.schedule).transaction(|(deliveries, schedule)| {
+            if let Some(due) = previous_due {
+                schedule.remove(schedule_key(due, id.as_bytes()))?;
+            }
+            deliveries.insert(id.as_bytes(), value.as_slice())?;
+            schedule.insert(schedule_key(delivery.due, id.as_bytes()), &[])?;
+            Ok::<_, ConflictableTransactionError>(())
+        })?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str, due: u64) -> Result<(), Error> {
+        (&self.deliveries, &self.schedule).transaction(|(deliveries, schedule)| {
+            deliveries.remove(id.as_bytes())?;
+            schedule.remove(schedule_key(due, id.as_bytes()))?;
+            Ok::<_, ConflictableTransactionError>(())
+        })?;
+        Ok(())
+    }
+
+    /// Attempts every delivery due by `now`, returning how many were sent and how many failed.
+    pub async fn deliver(&self, now: u64) -> Result<(usize, usize), Error> {
+        let due = self
+            .schedule
+            .range(..(now + 1).to_be_bytes())
+            .keys()
+            .collect::<Result<Vec<_>, _>>()?;
+        let attempts = due.iter().map(|index_key| {
+            let id = String::from_utf8_lossy(&index_key[8..]).into_owned();
+            async move {
+                let delivery = match self.deliveries.get(&id)? {
+                    Some(delivery) => serde_json::from_slice::<Delivery>(&delivery)?,
+                    None => {
+                        self.schedule.remove(index_key)?;
+                        return Ok(None);
+                    }
+                };
+                self.attempt(&id, delivery, now).await.map(Some)
+            }
+        });
+        let mut sent = 0;
+        let mut failed = 0;
+        for res in futures::future::join_all(attempts).await {
+            match res? {
+                Some(true) => sent += 1,
+                Some(false) => failed += 1,
+                None => (),
+            }
+        }
+        Ok((sent, failed))
+    }
+
+    /// Sends a delivery, returning whether the target accepted it. A failed delivery is
+    /// rescheduled, or dropped once it runs out of attempts.
+    async fn attempt(&self, id: &str, mut delivery: Delivery, now: u64) -> Result<bool, Error> {
+        let res = match self
+            .targets
+            .iter()
+            .find(|target| target.url == delivery.url)
+        {
+            Some(target) => self.post(target, &delivery.payload).await,
+            None => {
+                slog::info!(self.logger, "dropped webhook"; "reason" => "target removed");
+                self.remove(id, delivery.due)?;
+                return Ok(false);
+            }
+        };
+        let e = match res {
+            Ok(()) => {
+                self.remove(id, delivery.due)?;
+                return Ok(true);
+            }
+            Err(e) => e,
+        };
+        delivery.attempts += 1;
+        if delivery.attempts >= MAX_ATTEMPTS {
+            slog::warn!(
+                self.logger,
+                "dropped webhook";
+                "url" => &delivery.url,
+                "attempts" => delivery.attempts,
+                "reason" => %e,
+            );
+            self.remove(id, delivery.due)?;
+            return Ok(false);
+        }
+        let retry = FIRST_RETRY
+            .saturating_mul(1 << (delivery.attempts - 1))
+            .min(MAX_RETRY);
+        slog::info!(
+            self.logger,
+            "webhook failed";
+            "url" => &delivery.url,
+            "attempts" => delivery.attempts,
+            "retry-in" => retry,
+            "reason" => %e,
+        );
+        let previous_due = delivery.due;
+        delivery.due = now + retry;
+        self.put(id, &delivery, Some(previous_due))?;
+        Ok(false)
+    }
+
+    async fn post(&self, target: &Target, payload: &str) -> Result<(), Error> {
+        let req = Request::post(&target.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(
+                "x-webhook-signature",
+                format!("sha256={}", sign(&target.secret, payload)),
+            )
+            .body(Body::from(payload.to_owned()))?;
+        let res = tokio::time::timeout(TIMEOUT, self.client.request(req))
+            .await
+            .map_err(|_| anyhow!("timed out"))??;
+        if !res.status().is_success() {
+            return Err(anyhow!("target answered {}", res.status()).into());
+        }
+        Ok(())
+    }
+
+    /// The earliest time at which some delivery is due.
+    pub fn next_due(&self) -> Result<Option<u64>, Error> {
+        Ok(self
+            .schedule
+            .first()?
+            .and_then(|(key, _)| key.get(..8)?.try_into().ok())
+            .map(u64::from_be_bytes))
+    }
+}
@@ -0,0 +1,304 @@
+//! Crash injection for paste creation: uploads pastes from several connections while the server is
+//! repeatedly killed with SIGKILL at random moments, then restarts it and checks that every paste
+//! whose link was returned can still be read back intact.
+//!
+//! A killed process leaves its dirty pages to the kernel, so this exercises the ordering of
+//! creation (nothing is acknowledged before it is committed, and half-finished uploads are
+//! reconciled on restart) rather than the fsyncs themselves, which only a power cut can test.
+//!
+//! What a kill can show is the flush that strict durability adds for sled, which otherwise holds
+//! new writes in its own buffers for up to half a second. The server is killed the moment a paste
+//! is acknowledged: in strict mode none may be lost, and the relaxed control has to lose some, or
+//! the kill is not early enough to tell the two apart.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+const ROUNDS: usize = 6;
+const WRITERS: usize = 4;
+/// Bodies up to twice the 1 MiB inline limit, so uploads take both creation paths.
+const MAX_BODY: usize = 2 << 20;
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        if size == 0 {
+            return Some(out);
+        }
+        let chunk = body.get(line + 2..line + 2 + size)?;
+        out.extend_from_slice(chunk);
+        body = body.get(line + 2 + size + 2..)?;
+    }
+}
+
+fn request(port: u16, head: &str, body: &[u8]) -> std::io::Result<Response> {
+    let malformed = || std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed response");
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    stream.write_all(
+        format!(
+            "{}\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            head,
+            body.len()
+        )
+        .as_bytes(),
+    )?;
+    stream.write_all(body)?;
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = String::from_utf8_lossy(&raw[..split]).into_owned();
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+    let headers = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_owned(), value.trim().to_owned()))
+        })
+        .collect();
+    let mut res = Response {
+        status,
+        headers,
+        body: raw[split + 4..].to_vec(),
+    };
+    if res.header("transfer-encoding") == Some("chunked") {
+        res.body = dechunk(&res.body).ok_or_else(malformed)?;
+    } else if let Some(len) = res.header("content-length") {
+        if len.parse() != Ok(res.body.len()) {
+            return Err(malformed());
+        }
+    }
+    Ok(res)
+}
+
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start(dir: &Path) -> Server {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let log = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("server.log"))
+            .unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_burn-after-reading"))
+            .current_dir(dir)
+            .env("PORT", port.to_string())
+            .stdout(Stdio::null())
+            .stderr(log)
+            .spawn()
+            .unwrap();
+        let server = Server { child, port };
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(30),
+                "server did not start; see {}",
+                dir.join("server.log").display()
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+        server
+    }
+
+    fn login(&self) -> String {
+        let body = br#"{"user":"admin","password":"crash"}"#;
+        let res = request(
+            self.port,
+            "POST /api/login HTTP/1.1\r\nContent-Type: application/json",
+            body,
+        )
+        .unwrap();
+        assert_eq!(res.status, 204);
+        let cookie = res.header("set-cookie").expect("session cookie");
+        cookie.split(';').next().unwrap().to_owned()
+    }
+
+    fn read(&self, key: &str) -> Response {
+        request(self.port, &format!("GET /api/data/{} HTTP/1.1", key), &[]).unwrap()
+    }
+
+    fn kill(mut self) {
+        self.child.kill().unwrap();
+        self.child.wait().unwrap();
+    }
+}
+
+/// Uploads `body`, returning its key once the server has acknowledged it.
+fn upload(port: u16, cookie: &str, body: &[u8]) -> Option<String> {
+    let res = request(
+        port,
+        &format!(
+            "POST /api/data HTTP/1.1\r\nCookie: {}\r\nContent-Type: application/octet-stream",
+            cookie
+        ),
+        body,
+    )
+    .ok()?;
+    if res.status != 200 {
+        return None;
+    }
+    let res: serde_json::Value = serde_json::from_slice(&res.body).ok()?;
+    Some(res["hash"].as_str()?.to_owned())
+}
+
+/// Kills the server the moment each of `rounds` small pastes is acknowledged, and returns how many
+/// of them the restarted server has lost.
+fn kill_after_ack(storage: &str, durability: &str, rounds: usize) -> usize {
+    let dir = scratch(&format!("ack-{}-{}", storage, durability));
+    std::fs::write(
+        dir.join("start9/config.yaml"),
+        format!(
+            "password: crash\nstorage: {}\ndurability: {}\n",
+            storage, durability
+        ),
+    )
+    .unwrap();
+    let mut acked = Vec::new();
+    for _ in 0..rounds {
+        let server = Server::start(&dir);
+        let cookie = server.login();
+        // let the login's own writes reach the disk, so only the paste is at stake
+        thread::sleep(Duration::from_secs(1));
+        let mut body = vec![0; 1024];
+        rand::thread_rng().fill(&mut body[..]);
+        let key = upload(server.port, &cookie, &body).expect("upload was refused");
+        server.kill();
+        acked.push(key);
+    }
+    let server = Server::start(&dir);
+    let lost = acked
+        .iter()
+        .filter(|key| server.read(key).status != 200)
+        .count();
+    server.kill();
+    lost
+}
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("start9")).unwrap();
+    dir
+}
+
+fn crash_while_uploading(storage: &str) {
+    let dir = scratch(&format!("crash-{}", storage));
+    std::fs::write(
+        dir.join("start9/config.yaml"),
+        format!(
+            "password: crash\nstorage: {}\ndurability: strict\n",
+            storage
+        ),
+    )
+    .unwrap();
+    let acked = Arc::new(Mutex::new(Vec::new()));
+    for _ in 0..ROUNDS {
+        let server = Server::start(&dir);
+        let cookie = server.login();
+        let stop = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let port = server.port;
+                let cookie = cookie.clone();
+                let stop = stop.clone();
+                let acked = acked.clone();
+                thread::spawn(move || {
+                    let mut rng = rand::thread_rng();
+                    while !stop.load(Ordering::SeqCst) {
+                        let mut body = vec![0; rng.gen_range(1..=MAX_BODY)];
+                        rng.fill(&mut body[..]);
+                        if let Some(key) = upload(port, &cookie, &body) {
+                            acked.lock().unwrap().push((key, body));
+                        }
+                    }
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(
+            rand::thread_rng().gen_range(100..1000),
+        ));
+        // uploads in flight are cut off; only those already acknowledged must survive
+        server.kill();
+        stop.store(true, Ordering::SeqCst);
+        for writer in writers {
+            writer.join().unwrap();
+        }
+    }
+    let acked = acked.lock().unwrap();
+    assert!(!acked.is_empty(), "no upload was acknowledged");
+    let server = Server::start(&dir);
+    for (key, body) in acked.iter() {
+        let res = server.read(key);
+        assert_eq!(res.status, 200, "acknowledged paste {} was lost", key);
+        assert!(
+            &res.body == body,
+            "acknowledged paste {} was corrupted",
+            key
+        );
+    }
+    server.kill();
+}
+
+#[test]
+fn sled_keeps_acknowledged_pastes() {
+    crash_while_uploading("sled");
+}
+
+#[test]
+fn sqlite_keeps_acknowledged_pastes() {
+    crash_while_uploading("sqlite");
+}
+
+#[test]
+fn sled_strict_keeps_pastes_acknowledged_just_before_a_crash() {
+    assert_eq!(kill_after_ack("sled", "strict", 5), 0);
+}
+
+#[test]
+fn sled_relaxed_loses_pastes_acknowledged_just_before_a_crash() {
+    assert!(
+        kill_after_ack("sled", "relaxed", 5) > 0,
+        "relaxed mode kept every paste, so the kill cannot show what strict mode adds"
+    );
+}
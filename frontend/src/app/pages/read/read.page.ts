@@ -34,7 +34,6 @@ export class ReadPage extends ViewUtils implements OnInit {
 
   presentableContent: PresentablePaste
   rawContent: Paste
-  deleted = false
 
   readId: string
 
@@ -121,8 +120,7 @@ export class ReadPage extends ViewUtils implements OnInit {
 
   setContent (decryptedContent: ArrayBuffer, contentType: string) {
     this.presentableContent = this.toPresentablePaste(decryptedContent, contentType)
-    // don't block on this, but we delete it now as the data is fully cached in memory on the previous line
-    this.deletePaste()
+    // the server consumes a view when the paste is fetched, so there is nothing left to delete here
   }
 
   async burnTransition () {
@@ -132,26 +130,13 @@ export class ReadPage extends ViewUtils implements OnInit {
     this.presentableContent = undefined
   }
 
-  async deletePaste (): Promise<boolean> {
-    if (this.deleted) return true
-    return this.apiService.delPaste(this.readId)
-      .then(() => { this.deleted = true; return true })
-      .catch(e => {
-        this.alertError(new Error('Unable to burn content: ' + e.message))
-        return false
-    } )
-  }
-
   burning = false
   async burnNow () {
     if (this.burning) return
     this.burning = true
-    // if for whatever reason the data hasn't be deleted yet...
-    return this.loaderService.displayDuringP(
-      this.deletePaste(),
-    ).then(t => t && this.burnTransition())
-    .catch(e => this.alertError(e))
-    .finally(() => this.burning = false)
+    // the server consumed this view when the paste was fetched; only the local copy is left
+    return this.burnTransition()
+      .finally(() => this.burning = false)
   }
 
   async downloadBlob () {
@@ -1,11 +1,18 @@
 import { Paste } from '../paste/paste'
 
+export interface NewPasteRes {
+  hash: string
+  revocation_token: string
+  // effective expiration, in seconds since the epoch
+  expiration: number
+}
+
 export abstract class ApiService {
   abstract login (password: string): Promise<boolean>
   abstract logout (): Promise<void>
   abstract getPaste (hash: string): Promise<Paste | null>
-  abstract delPaste (hash: string): Promise<void>
-  abstract newPaste (paste: Paste, expireAt: Date): Promise<{ hash: string }>
+  abstract revokePaste (revocationToken: string): Promise<void>
+  abstract newPaste (paste: Paste, expireAt: Date): Promise<NewPasteRes>
 
   async initialize (): Promise<any> { }
 }
@@ -1,5 +1,5 @@
 import { Paste } from '../paste/paste'
-import { ApiService } from './api.service'
+import { ApiService, NewPasteRes } from './api.service'
 import { AuthState, AuthStore } from '../auth.store'
 
 export class LiveApi extends ApiService {
@@ -51,27 +51,31 @@ export class LiveApi extends ApiService {
         }
     }
 
-    async delPaste (hash: string): Promise<void> {
-        const res = await fetch(`/api/data/${hash}`, { method: 'DELETE' })
+    async revokePaste (revocationToken: string): Promise<void> {
+        const res = await fetch(`/api/revoke/${revocationToken}`, { method: 'DELETE' })
         switch (res.status) {
             case 200:
             case 204:
+            case 404:
                 return
             default:
                 throw { message: `${res.status} ${res.statusText}`, status: res.status, url: res.url }
         }
     }
 
-    async newPaste (p: Paste, expireAt: Date): Promise<{ hash: string }> {
-        const epochSec = Math.floor( expireAt.getTime() / 1000 )
+    async newPaste (p: Paste, expireAt: Date): Promise<NewPasteRes> {
+        // a relative lifetime keeps uploads working when this clock disagrees with the server's
+        const ttlSec = Math.floor( (expireAt.getTime() - Date.now()) / 1000 )
         const res = await this.fetchAuth(`/api/data`, {
             method: 'POST',
-            headers: { 'Content-Type': p.contentType, 'x-paste-expiration': `${epochSec}`},
+            headers: { 'Content-Type': p.contentType, 'x-paste-ttl': `${ttlSec}`},
             body: await p.content,
         })
         switch (res.status) {
             case 200:
                 return res.json()
+            case 400:
+                throw { message: await res.text(), status: res.status, url: res.url }
             default:
                 throw { message: `${res.status} ${res.statusText}`, status: res.status, url: res.url }
         }
@@ -1,6 +1,6 @@
 import { addPrefix, Paste } from '../paste/paste'
 import { pauseFor } from 'src/app/util/misc.util'
-import { ApiService } from './api.service'
+import { ApiService, NewPasteRes } from './api.service'
 import { encryptArrayBuffer } from '../paste/crypto'
 
 const crypto = window.crypto
@@ -47,14 +47,18 @@ export class MockApi extends ApiService {
         return this.pastes.get(hash) || null
     }
 
-    async delPaste (hash: string): Promise<void> {
+    async revokePaste (revocationToken: string): Promise<void> {
         await pauseFor(1000)
-        this.pastes.delete(hash)
+        this.pastes.delete(revocationToken.replace('revoke-', ''))
     }
 
-    async newPaste (paste: Paste, expireAt: Date): Promise<{ hash: string }> {
+    async newPaste (paste: Paste, expireAt: Date): Promise<NewPasteRes> {
         this.hash ++
         this.pastes.set(String(this.hash), paste)
-        return { hash: String(this.hash) }
+        return {
+            hash: String(this.hash),
+            revocation_token: `revoke-${this.hash}`,
+            expiration: Math.floor(expireAt.getTime() / 1000),
+        }
     }
 }
@@ -4,6 +4,7 @@ export const getConfig = compat.getConfig({
   "password": {
     "type": "string",
     "name": "Password",
+    "description": "Password of the built-in admin account, which logs in as \"admin\" and can create accounts for others.",
     "nullable": false,
     "copyable": true,
     "masked": true,
@@ -11,5 +12,221 @@ export const getConfig = compat.getConfig({
       "len": 22,
       "charset": "a-z,A-Z,0-9"
     }
+  },
+  "key-length": {
+    "type": "number",
+    "name": "Link Length",
+    "description": "Number of random bytes used to generate each paste link. Longer links are harder to guess.",
+    "nullable": false,
+    "range": "[16,64]",
+    "integral": true,
+    "units": "bytes",
+    "default": 24
+  },
+  "min-lifetime": {
+    "type": "number",
+    "name": "Minimum Lifetime",
+    "description": "Shortest time a paste may be set to live before it expires.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "seconds",
+    "default": 60
+  },
+  "default-lifetime": {
+    "type": "number",
+    "name": "Default Lifetime",
+    "description": "How long a paste lives when the uploader does not choose. Must be between the minimum and maximum lifetimes.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "seconds",
+    "default": 86400
+  },
+  "max-lifetime": {
+    "type": "number",
+    "name": "Maximum Lifetime",
+    "description": "Longest time a paste may be set to live before it expires.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "seconds",
+    "default": 2592000
+  },
+  "max-paste-size": {
+    "type": "number",
+    "name": "Maximum Paste Size",
+    "description": "Largest paste that may be uploaded. Larger uploads are cut off and rejected.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "MiB",
+    "default": 1024
+  },
+  "max-total-size": {
+    "type": "number",
+    "name": "Storage Quota",
+    "description": "Combined size of all stored pastes beyond which new uploads are rejected. Leave empty for no quota.",
+    "nullable": true,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "MiB"
+  },
+  "min-free-space": {
+    "type": "number",
+    "name": "Minimum Free Space",
+    "description": "Uploads are rejected when they would leave less than this much free space on the disk.",
+    "nullable": false,
+    "range": "[0,*)",
+    "integral": true,
+    "units": "MiB",
+    "default": 512
+  },
+  "storage": {
+    "type": "enum",
+    "name": "Storage Backend",
+    "description": "Where pastes are kept. Sled and SQLite persist across restarts; in-memory keeps nothing on disk and loses every paste when the service stops. Existing pastes are not moved when this is changed.",
+    "values": ["sled", "sqlite", "memory"],
+    "value-names": {
+      "sled": "Sled",
+      "sqlite": "SQLite",
+      "memory": "In-Memory"
+    },
+    "default": "sled"
+  },
+  "secure-erase": {
+    "type": "boolean",
+    "name": "Secure Erase",
+    "description": "Overwrite the contents of pastes before deleting them, and rewrite the database on startup so the details of burned pastes are scrubbed from disk. With sled storage, small pastes are stored as files like large ones, so their contents can be overwritten as soon as they burn; only details such as their size, type and label stay in the database files until the next restart. Makes deletion and startup slower. Overwriting cannot guarantee erasure on SSDs or copy-on-write filesystems.",
+    "default": false
+  },
+  "durability": {
+    "type": "enum",
+    "name": "Durability",
+    "description": "Strict syncs every new paste to disk before its link is shown, so a power loss cannot lose it. Relaxed leaves writing to the background, which makes uploads faster but can lose pastes created just before a power loss.",
+    "values": ["strict", "relaxed"],
+    "value-names": {
+      "strict": "Strict",
+      "relaxed": "Relaxed"
+    },
+    "default": "strict"
+  },
+  "download-grace": {
+    "type": "number",
+    "name": "Download Grace Period",
+    "description": "Large pastes can be downloaded in parts, and a view is only used up once every byte has been sent. A download that never finishes uses up its view after this long.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "seconds",
+    "default": 3600
+  },
+  "passphrase-attempts": {
+    "type": "number",
+    "name": "Passphrase Attempts",
+    "description": "Pastes can be given an access passphrase that the server checks before serving them. A paste burns once this many wrong passphrases have been tried.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "default": 5
+  },
+  "inbox-recipient": {
+    "type": "string",
+    "name": "Inbox Public Key",
+    "description": "An age public key, as made by age-keygen. When set, anyone can leave messages and files in the inbox without logging in, and each is stored encrypted to this key. Only the matching secret key can read them, and it never needs to be on the server. Leave empty to turn the inbox off.",
+    "nullable": true,
+    "pattern": "^age1[02-9ac-hj-np-z]{58}$",
+    "pattern-description": "Must be an age X25519 public key, starting with age1."
+  },
+  "inbox-max-size": {
+    "type": "number",
+    "name": "Inbox Message Size",
+    "description": "Largest message that may be left in the inbox.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "MiB",
+    "default": 16
+  },
+  "inbox-rate-limit": {
+    "type": "number",
+    "name": "Inbox Rate Limit",
+    "description": "Messages each source may leave in the inbox per hour. Sources reach the inbox over Tor, so they are not told apart by address: each registers first by solving a proof-of-work puzzle, and its messages are counted against the token it gets back. Messages that are refused do not count.",
+    "nullable": false,
+    "range": "[1,*)",
+    "integral": true,
+    "units": "messages per hour",
+    "default": 10
+  },
+  "inbox-difficulty": {
+    "type": "number",
+    "name": "Inbox Proof of Work",
+    "description": "Leading zero bits a source's proof of work must reach before it may leave messages. Each extra bit doubles the work. The default of 20 takes about a million hashes; 0 lets anyone register as many sources as they like, which makes the per-source limit meaningless.",
+    "nullable": false,
+    "range": "[0,32]",
+    "integral": true,
+    "units": "bits",
+    "default": 20
+  },
+  "webhooks": {
+    "type": "list",
+    "subtype": "object",
+    "name": "Webhooks",
+    "description": "URLs that are sent a signed JSON POST whenever something happens to a paste. Payloads name pastes by the id returned at creation, never by their link, and carry an x-webhook-signature header holding the HMAC-SHA256 of the body under the webhook's secret. Failed deliveries are retried for several hours.",
+    "range": "[0,*)",
+    "default": [],
+    "spec": {
+      "unique-by": "url",
+      "display-as": "{{url}}",
+      "spec": {
+        "url": {
+          "type": "string",
+          "name": "URL",
+          "nullable": false,
+          "pattern": "^https?://.+",
+          "pattern-description": "Must be an http or https URL."
+        },
+        "secret": {
+          "type": "string",
+          "name": "Signing Secret",
+          "nullable": false,
+          "copyable": true,
+          "masked": true,
+          "default": {
+            "len": 32,
+            "charset": "a-z,A-Z,0-9"
+          }
+        },
+        "events": {
+          "type": "list",
+          "subtype": "enum",
+          "name": "Events",
+          "range": "[0,*)",
+          "default": ["created", "viewed", "burned", "expired-unread", "revoked", "locked-out", "low-disk"],
+          "spec": {
+            "values": ["created", "viewed", "burned", "expired-unread", "revoked", "locked-out", "low-disk"],
+            "value-names": {
+              "created": "Paste created",
+              "viewed": "Paste viewed",
+              "burned": "Last view taken",
+              "expired-unread": "Expired unread",
+              "revoked": "Paste revoked",
+              "locked-out": "Burned after wrong passphrases",
+              "low-disk": "Low disk space"
+            }
+          }
+        }
+      }
+    }
+  },
+  "low-disk-warning": {
+    "type": "number",
+    "name": "Low Disk Warning",
+    "description": "Webhooks are sent a low-disk event when free space on the disk drops below this.",
+    "nullable": false,
+    "range": "[0,*)",
+    "integral": true,
+    "units": "MiB",
+    "default": 2048
   }
 })